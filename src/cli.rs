@@ -0,0 +1,287 @@
+use std::path::{Path, PathBuf};
+
+use gameboy::io::ppu::{ComboButton, ComboDirection, Preset};
+
+const USAGE: &str = "usage: gameboy [options] <rom>
+
+options:
+    --scale <n>           window scale factor (default 4)
+    --boot-rom <file>     boot rom to run instead of the built-in one
+    --skip-boot           start at the cartridge entry point
+    --headless            run without a window or audio
+    --frames <n>          number of frames to run before exiting
+    --input <file>        headless input script of `<frame> <buttons>` lines
+    --screenshot <file>   write the last frame as a png when running headless
+    --until-serial <text> stop headless runs once the text is sent over the link port,
+                          exiting with an error on `Failed` or if `--frames` runs out
+    --palette <name>      dmg colours, one of `classic`, `pocket`, `light` or `contrast`
+    --palette-file <file> dmg colours from lines of `<bg|obj0|obj1|all> = <colours>`, where
+                          colours are a palette name or four rgb hex values
+    --compat-palette <p>  colourise dmg games like a game boy color, either `title` to pick
+                          from the cartridge title or a boot combo such as `left+b`
+    --mute                disable audio output
+    --speed <n>           emulation speed multiplier (default 1)
+    --save-dir <dir>      directory to keep save files in, instead of next to the rom
+    --link-listen <port>  wait for another emulator to connect a link cable
+    --link-connect <addr> connect a link cable to another emulator
+    --printer <dir>       attach a game boy printer, saving printouts in the directory
+    --pair <rom>          run a second linked machine in the same window
+    --pair-input <file>   headless input script for the second machine
+    --info                print the cartridge header and exit
+    -h, --help            print this message";
+
+/// how a dmg game's colours are picked, as the cgb boot rom does
+#[derive(Clone, Copy)]
+pub enum CompatPalette {
+    /// from a hash of the cartridge title
+    Title,
+    /// from a direction, optionally with a or b, held during the boot logo
+    Combo(ComboDirection, Option<ComboButton>),
+}
+
+pub struct Options {
+    pub rom: PathBuf,
+    pub scale: f32,
+    pub boot_rom: Option<PathBuf>,
+    pub skip_boot: bool,
+    pub headless: bool,
+    /// exit after this many frames, otherwise run until closed
+    pub frames: Option<u32>,
+    pub input: Option<PathBuf>,
+    pub screenshot: Option<PathBuf>,
+    /// serial output that ends a headless run early, as blargg's test roms send
+    pub until_serial: Option<String>,
+    pub palette: Option<Preset>,
+    /// custom dmg colours, read again when reloaded at runtime
+    pub palette_file: Option<PathBuf>,
+    pub compat_palette: Option<CompatPalette>,
+    pub mute: bool,
+    pub speed: f32,
+    pub save_dir: Option<PathBuf>,
+    pub link_listen: Option<u16>,
+    pub link_connect: Option<String>,
+    /// directory for printouts, attaches a printer to the link port
+    pub printer: Option<PathBuf>,
+    /// rom for a second machine, linked to the first in-process
+    pub pair: Option<PathBuf>,
+    pub pair_input: Option<PathBuf>,
+    pub info: bool,
+}
+
+impl Options {
+    /// parse options from the process arguments, printing usage & exiting on error
+    pub fn from_args() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(Some(options)) => options,
+            Ok(None) => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("error: {}\n\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// returns `None` if help was requested
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut rom = None;
+        let mut options = Self {
+            rom: PathBuf::new(),
+            scale: 4.0,
+            boot_rom: None,
+            skip_boot: false,
+            headless: false,
+            frames: None,
+            input: None,
+            screenshot: None,
+            until_serial: None,
+            palette: None,
+            palette_file: None,
+            compat_palette: None,
+            mute: false,
+            speed: 1.0,
+            save_dir: None,
+            link_listen: None,
+            link_connect: None,
+            printer: None,
+            pair: None,
+            pair_input: None,
+            info: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{}`", name))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--scale" => options.scale = parse_number(&arg, &value(&arg)?)?,
+                "--boot-rom" => options.boot_rom = Some(value(&arg)?.into()),
+                "--skip-boot" => options.skip_boot = true,
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = Some(parse_number(&arg, &value(&arg)?)?),
+                "--input" => options.input = Some(value(&arg)?.into()),
+                "--screenshot" => options.screenshot = Some(value(&arg)?.into()),
+                "--until-serial" => options.until_serial = Some(value(&arg)?),
+                "--palette" => {
+                    let name = value(&arg)?;
+                    options.palette = Some(
+                        Preset::from_name(&name)
+                            .ok_or_else(|| format!("unknown palette `{}`", name))?,
+                    )
+                }
+                "--palette-file" => options.palette_file = Some(value(&arg)?.into()),
+                "--compat-palette" => {
+                    options.compat_palette = Some(parse_compat_palette(&value(&arg)?)?)
+                }
+                "--mute" => options.mute = true,
+                "--speed" => options.speed = parse_number(&arg, &value(&arg)?)?,
+                "--save-dir" => options.save_dir = Some(value(&arg)?.into()),
+                "--link-listen" => options.link_listen = Some(parse_number(&arg, &value(&arg)?)?),
+                "--link-connect" => options.link_connect = Some(value(&arg)?),
+                "--printer" => options.printer = Some(value(&arg)?.into()),
+                "--pair" => options.pair = Some(value(&arg)?.into()),
+                "--pair-input" => options.pair_input = Some(value(&arg)?.into()),
+                "--info" => options.info = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ if rom.is_some() => return Err(format!("unexpected argument `{}`", arg)),
+                _ => rom = Some(PathBuf::from(arg)),
+            }
+        }
+
+        options.rom = rom.ok_or("no rom given")?;
+
+        if options.headless && options.frames.is_none() {
+            return Err("`--headless` requires `--frames`".to_string());
+        }
+
+        if !cfg!(feature = "frontend") && !options.headless && !options.info {
+            return Err(
+                "built without the `frontend` feature, only `--headless` runs are available"
+                    .to_string(),
+            );
+        }
+
+        if options.until_serial.is_some() && !options.headless {
+            return Err("`--until-serial` requires `--headless`".to_string());
+        }
+
+        if options.link_listen.is_some() && options.link_connect.is_some() {
+            return Err("`--link-listen` and `--link-connect` can't be used together".to_string());
+        }
+
+        let links = [
+            options.link_listen.is_some() || options.link_connect.is_some(),
+            options.printer.is_some(),
+            options.pair.is_some(),
+            options.until_serial.is_some(),
+        ];
+        if links.iter().filter(|used| **used).count() > 1 {
+            return Err(
+                "only one of a link cable, `--printer`, `--pair` or `--until-serial` can be used"
+                    .to_string(),
+            );
+        }
+
+        let palettes = [
+            options.palette.is_some(),
+            options.palette_file.is_some(),
+            options.compat_palette.is_some(),
+        ];
+        if palettes.iter().filter(|used| **used).count() > 1 {
+            return Err(
+                "only one of `--palette`, `--palette-file` or `--compat-palette` can be used"
+                    .to_string(),
+            );
+        }
+
+        if options.pair.is_none() && options.pair_input.is_some() {
+            return Err("`--pair-input` requires `--pair`".to_string());
+        }
+
+        if options.scale <= 0.0 {
+            return Err("scale must be greater than zero".to_string());
+        }
+
+        if options.speed <= 0.0 {
+            return Err("speed must be greater than zero".to_string());
+        }
+
+        Ok(Some(options))
+    }
+
+    /// save file for the rom, placed in the save directory if one was given
+    pub fn save_path(&self) -> PathBuf {
+        self.sibling_path("sav")
+    }
+
+    /// save file for the second machine, kept apart from the first when both run the same rom
+    pub fn pair_save_path(&self) -> Option<PathBuf> {
+        let pair = self.pair.as_ref()?;
+
+        if *pair == self.rom {
+            Some(self.sibling_path("2.sav"))
+        } else {
+            Some(self.sibling_path_of(pair, "sav"))
+        }
+    }
+
+    /// save state file for a numbered slot, kept alongside the save file
+    #[cfg(feature = "frontend")]
+    pub fn state_path(&self, slot: u8) -> PathBuf {
+        self.sibling_path(&format!("ss{}", slot))
+    }
+
+    fn sibling_path(&self, extension: &str) -> PathBuf {
+        self.sibling_path_of(&self.rom, extension)
+    }
+
+    fn sibling_path_of(&self, rom: &Path, extension: &str) -> PathBuf {
+        match &self.save_dir {
+            Some(dir) => dir
+                .join(rom.file_name().unwrap_or_default())
+                .with_extension(extension),
+            None => rom.with_extension(extension),
+        }
+    }
+}
+
+/// `title`, or a direction followed by an optional `+a` or `+b`
+fn parse_compat_palette(value: &str) -> Result<CompatPalette, String> {
+    if value == "title" {
+        return Ok(CompatPalette::Title);
+    }
+
+    let invalid = || format!("invalid compatibility palette `{}`", value);
+    let (direction, button) = match value.split_once('+') {
+        Some((direction, button)) => (direction, Some(button)),
+        None => (value, None),
+    };
+
+    let direction = match direction {
+        "right" => ComboDirection::Right,
+        "left" => ComboDirection::Left,
+        "up" => ComboDirection::Up,
+        "down" => ComboDirection::Down,
+        _ => return Err(invalid()),
+    };
+
+    let button = match button {
+        None => None,
+        Some("a") => Some(ComboButton::A),
+        Some("b") => Some(ComboButton::B),
+        Some(_) => return Err(invalid()),
+    };
+
+    Ok(CompatPalette::Combo(direction, button))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, name))
+}
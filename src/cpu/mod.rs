@@ -1,4 +1,10 @@
-use crate::io::{cartridge::Cartridge, Bus};
+use std::mem;
+
+use crate::{
+    cpu::interrupt::Interrupt,
+    io::{cartridge::Cartridge, Bus},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
 
 use log::{error, info};
 
@@ -15,10 +21,14 @@ pub struct Cpu {
     pub flags: Flags,
     /// interrupt enable
     it_master_enable: bool,
-    /// set to true if interrupt should be delayed one instruction
+    /// set by ei, interrupts are enabled once the instruction after it has run
     it_master_enable_next: bool,
-    /// cpu is stopped
+    /// cpu is idle until an interrupt is pending
     halted: bool,
+    /// next opcode fetch doesn't advance pc, after a halt with interrupts disabled & pending
+    halt_bug: bool,
+    /// cpu & lcd are stopped until a button is pressed
+    stopped: bool,
     /// access other parts of the machine
     pub bus: Bus,
     /// elapsed cycles after instruction
@@ -54,39 +64,79 @@ impl Cpu {
             it_master_enable: false,
             it_master_enable_next: false,
             halted: false,
+            halt_bug: false,
+            stopped: false,
             bus: Bus::new(cart),
             cycles: 0,
         }
     }
 
+    /// start from the register state left behind by the boot rom
+    pub fn skip_boot(&mut self) {
+        self.boot_registers();
+        self.registers.pc = 0x0100;
+        self.bus.skip_boot();
+    }
+
+    /// registers as the boot rom leaves them when it hands over to the cartridge
+    fn boot_registers(&mut self) {
+        // games check a for 0x11 to detect a gameboy color
+        self.set_af(if self.bus.cgb { 0x11b0 } else { 0x01b0 });
+        self.set_bc(0x0013);
+        self.set_de(0x00d8);
+        self.set_hl(0x014d);
+        self.registers.sp = 0xfffe;
+    }
+
     pub fn machine_cycle(&mut self) -> u8 {
         self.cycles = 0;
 
-        if self.it_master_enable {
-            if self.handle_interrupt() {
-                // interrupt was handled, return cycles
+        if self.stopped {
+            // nothing is clocked, only a button press starts the oscillator again
+            if !self.bus.joypad.interrupt {
+                self.cycles += 4;
                 return self.cycles;
             }
-        } else {
-            self.it_master_enable = self.it_master_enable_next;
+
+            self.stopped = false;
+            self.bus.collect_interrupts();
+        }
+
+        if self.bus.dma_stall > 0 {
+            // vram dma has the bus, the cpu can't run until it's done
+            self.delay(1);
+            self.bus.dma_stall = self.bus.dma_stall.saturating_sub(4);
+            return self.cycles;
+        }
+
+        if self.it_master_enable && self.handle_interrupt() {
+            // interrupt was handled, return cycles
+            return self.cycles;
         }
 
         if self.halted {
-            self.bus.tick();
-            self.cycles += 1;
+            self.delay(1);
 
             // even if the interrupt flag is disabled
             // the halt instruction should still end
-            if !self.it_master_enable && self.check_interrupt() {
+            if self.check_interrupt() {
                 self.halted = false;
-            } else {
-                return self.cycles;
             }
+
+            return self.cycles;
         }
 
+        // ei only takes effect after the following instruction, which may be a di cancelling it
+        let enable_interrupts = self.it_master_enable_next;
+
         // fetch next instruction
         self.execute_next();
 
+        if enable_interrupts && self.it_master_enable_next {
+            self.it_master_enable = true;
+            self.it_master_enable_next = false;
+        }
+
         self.cycles
     }
 
@@ -220,6 +270,11 @@ impl Cpu {
     pub fn store_byte(&mut self, address: u16, value: u8) {
         self.delay(1);
         self.bus.store_byte(address, value);
+
+        if self.bus.take_boot_unmapped() && self.bus.cgb {
+            // the built-in boot rom is the dmg one, hand over in the cgb state games look for
+            self.boot_registers();
+        }
     }
 
     pub fn store_word(&mut self, address: u16, value: u16) {
@@ -256,8 +311,10 @@ impl Cpu {
 
         // self.trace();
 
-        // increment program counter
-        self.registers.pc = self.registers.pc.wrapping_add(1);
+        // increment program counter, unless the halt bug repeats this byte
+        if !mem::take(&mut self.halt_bug) {
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
 
         // select opcode depending on whether it has 0xcb prefix
         if opcode != 0xcb {
@@ -287,41 +344,55 @@ impl Cpu {
         self.advance(machine_cycles * 4);
     }
 
-    /// handles interrupt, returns whether it was actually handled
+    /// dispatches the highest priority pending interrupt, returns whether one was
     fn handle_interrupt(&mut self) -> bool {
-        if self.bus.it_enable.vblank && self.bus.ppu.vblank_int {
-            self.bus.ppu.vblank_int = false;
-            self.interrupt(interrupt::irq_vector::VBLANK);
-            true
-        } else if self.bus.it_enable.lcdc && self.bus.ppu.lcd_stat_int {
-            self.bus.ppu.lcd_stat_int = false;
-            self.interrupt(interrupt::irq_vector::LCDC);
-            true
-        } else if self.bus.it_enable.timer && self.bus.timer.interrupt {
-            self.bus.timer.interrupt = false;
-            self.interrupt(interrupt::irq_vector::TIMER);
-            true
-        } else {
-            // interrupt was not handled
-            false
+        if !self.check_interrupt() {
+            return false;
         }
+
+        self.halted = false;
+        self.stopped = false;
+        self.it_master_enable = false;
+        self.it_master_enable_next = false;
+
+        // pc is decremented & the fetched opcode thrown away
+        self.delay(2);
+
+        let pc = self.registers.pc;
+        self.push_byte((pc >> 8) as u8);
+
+        // the vector is picked after the high byte is pushed, if that write landed on IE
+        // & disabled every pending interrupt the cpu jumps to 0x0000 instead
+        let pending = self.pending_interrupts();
+        self.push_byte(pc as u8);
+
+        self.registers.pc = match pending.trailing_zeros() {
+            0 => interrupt::irq_vector::VBLANK,
+            1 => interrupt::irq_vector::LCDC,
+            2 => interrupt::irq_vector::TIMER,
+            3 => interrupt::irq_vector::SERIAL,
+            4 => interrupt::irq_vector::JOYPAD,
+            _ => 0x0000,
+        };
+
+        if pending != 0 {
+            let acknowledged = pending & pending.wrapping_neg();
+            self.bus.it_flag = Interrupt::from(u8::from(self.bus.it_flag) & !acknowledged);
+        }
+
+        self.delay(1);
+
+        true
     }
 
     /// returns whether the interrupt can be handled
     fn check_interrupt(&mut self) -> bool {
-        (self.bus.it_enable.vblank && self.bus.ppu.vblank_int)
-            || (self.bus.it_enable.lcdc && self.bus.ppu.lcd_stat_int)
-            || (self.bus.it_enable.timer && self.bus.timer.interrupt)
+        self.pending_interrupts() != 0
     }
 
-    fn interrupt(&mut self, address: u16) {
-        self.halted = false;
-        self.it_master_enable = false;
-        self.it_master_enable_next = false;
-
-        self.push_word(self.registers.pc);
-        self.delay(6);
-        self.registers.pc = address;
+    /// interrupts both requested & enabled, the lowest bit has the highest priority
+    fn pending_interrupts(&self) -> u8 {
+        u8::from(self.bus.it_flag) & u8::from(self.bus.it_enable)
     }
 
     pub fn fault(&mut self, message: &str) {
@@ -396,3 +467,137 @@ impl From<Flags> for u8 {
             | ((flags.c as u8) << 4)
     }
 }
+
+impl Snapshot for Cpu {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.registers.a);
+        w.u8(self.registers.b);
+        w.u8(self.registers.c);
+        w.u8(self.registers.d);
+        w.u8(self.registers.e);
+        w.u8(self.registers.h);
+        w.u8(self.registers.l);
+        w.u16(self.registers.pc);
+        w.u16(self.registers.sp);
+        w.u8(self.flags.into());
+        w.bool(self.it_master_enable);
+        w.bool(self.it_master_enable_next);
+        w.bool(self.halted);
+        w.bool(self.halt_bug);
+        w.bool(self.stopped);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.registers.a = r.u8()?;
+        self.registers.b = r.u8()?;
+        self.registers.c = r.u8()?;
+        self.registers.d = r.u8()?;
+        self.registers.e = r.u8()?;
+        self.registers.h = r.u8()?;
+        self.registers.l = r.u8()?;
+        self.registers.pc = r.u16()?;
+        self.registers.sp = r.u16()?;
+        self.flags = Flags::from(r.u8()?);
+        self.it_master_enable = r.bool()?;
+        self.it_master_enable_next = r.bool()?;
+        self.halted = r.bool()?;
+        self.halt_bug = r.bool()?;
+        self.stopped = r.bool()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::cartridge::CartridgeHeader;
+
+    use super::*;
+
+    /// di, enable only the timer interrupt & leave it pending
+    const PENDING_TIMER: [u8; 7] = [0xf3, 0x3e, 0x04, 0xe0, 0xff, 0xe0, 0x0f];
+    /// inc b, reti
+    const COUNTING_HANDLER: [u8; 2] = [0x04, 0xd9];
+
+    /// run `code` from the entry point for a while, with `handler` at the timer vector
+    fn run(code: &[&[u8]], handler: &[u8]) -> Cpu {
+        let code = code.concat();
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x50..0x50 + handler.len()].copy_from_slice(handler);
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+        rom[0x14d] = CartridgeHeader::compute_header_checksum(&rom);
+
+        let mut cpu = Cpu::new(Cartridge::from_bytes(rom).unwrap());
+        cpu.skip_boot();
+
+        for _ in 0..100 {
+            cpu.machine_cycle();
+        }
+
+        cpu
+    }
+
+    #[test]
+    fn halt_bug_repeats_the_next_byte() {
+        // xor a, halt, inc a, jr -2
+        let cpu = run(
+            &[&PENDING_TIMER, &[0xaf, 0x76, 0x3c, 0x18, 0xfe]],
+            &COUNTING_HANDLER,
+        );
+
+        assert_eq!(cpu.registers.a, 2);
+        assert_eq!(cpu.registers.b, 0);
+        assert!(!cpu.halted);
+    }
+
+    #[test]
+    fn ei_halt_services_the_interrupt_then_halts() {
+        // ld bc,0, ei, halt, inc c, jr -2
+        let cpu = run(
+            &[
+                &PENDING_TIMER,
+                &[0x01, 0x00, 0x00, 0xfb, 0x76, 0x0c, 0x18, 0xfe],
+            ],
+            &COUNTING_HANDLER,
+        );
+
+        assert_eq!(cpu.registers.b, 1);
+        assert_eq!(cpu.registers.c, 0);
+        assert!(cpu.halted);
+    }
+
+    #[test]
+    fn di_cancels_a_pending_ei() {
+        // ld b,0, ei, di, jr -2
+        let mut cpu = run(
+            &[&PENDING_TIMER, &[0x06, 0x00, 0xfb, 0xf3, 0x18, 0xfe]],
+            &COUNTING_HANDLER,
+        );
+
+        assert_eq!(cpu.registers.b, 0);
+        assert!(!cpu.it_master_enable);
+        assert_ne!(cpu.fetch_byte(0xff0f) & 0x04, 0);
+    }
+
+    #[test]
+    fn cgb_cart_leaves_the_boot_rom_in_the_cgb_state() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        rom[0x14d] = CartridgeHeader::compute_header_checksum(&rom);
+
+        // ld a,1, ldh (0x50),a
+        let mut cpu = Cpu::new(Cartridge::from_bytes(rom).unwrap());
+        cpu.bus.set_boot_rom(vec![0x3e, 0x01, 0xe0, 0x50]);
+        cpu.machine_cycle();
+        cpu.machine_cycle();
+
+        assert!(!cpu.bus.boot);
+        assert_eq!(cpu.get_af(), 0x11b0);
+        assert_eq!(cpu.get_bc(), 0x0013);
+        assert_eq!(cpu.get_de(), 0x00d8);
+        assert_eq!(cpu.get_hl(), 0x014d);
+        assert_eq!(cpu.registers.sp, 0xfffe);
+    }
+}
@@ -32,6 +32,7 @@ pub fn execute(cpu: &mut Cpu, opcode: u8) {
         "0011_0010" => lsm::ldd_mhl_a(cpu),
         "0011_1010" => lsm::ldd_a_mhl(cpu),
         "0000_1000" => lsm::ld_mu16_sp(cpu),
+        "0111_0110" => misc::halt(cpu),
         "01xx_xyyy" => lsm::ld_r8_r8(cpu, x, y),
         "1000_0xxx" => alu::add_a_r8(cpu, x),
         "1000_1xxx" => alu::adc_a_r8(cpu, x),
@@ -116,11 +117,25 @@ mod misc {
 
     pub fn di(cpu: &mut Cpu) {
         cpu.it_master_enable = false;
+        // also cancels an ei that hasn't taken effect yet
+        cpu.it_master_enable_next = false;
     }
 
     pub fn ei(cpu: &mut Cpu) {
         cpu.it_master_enable_next = true;
     }
+
+    pub fn halt(cpu: &mut Cpu) {
+        if cpu.it_master_enable || !cpu.check_interrupt() {
+            cpu.halted = true;
+        } else if cpu.it_master_enable_next {
+            // ei right before: the interrupt is serviced straight away, returning to the halt
+            cpu.registers.pc = cpu.registers.pc.wrapping_sub(1);
+        } else {
+            // halt bug: the cpu doesn't halt & reads the next byte twice
+            cpu.halt_bug = true;
+        }
+    }
 }
 
 /// load, store & move instructions
@@ -544,7 +559,15 @@ mod ctrl {
     use crate::cpu::Cpu;
 
     pub fn stop(cpu: &mut Cpu) {
-        cpu.bus.switch_speed();
+        // stop is followed by a padding byte that is skipped
+        super::next_byte(cpu);
+
+        cpu.bus.timer.reset_div();
+
+        // an armed speed switch is all stop does, otherwise it waits for a button
+        if !cpu.bus.switch_speed() {
+            cpu.stopped = true;
+        }
     }
 
     pub fn jr_i8(cpu: &mut Cpu) {
@@ -598,7 +621,6 @@ mod ctrl {
         let address = cpu.pop_word();
         cpu.registers.pc = address;
         cpu.it_master_enable = true;
-        cpu.it_master_enable_next = true;
         cpu.delay(1);
     }
 
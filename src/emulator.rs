@@ -0,0 +1,198 @@
+use crate::{
+    cpu::Cpu,
+    io::{
+        cartridge::Cartridge,
+        ppu::{Colour, DmgPalettes},
+        serial::{SerialCapture, SerialLink},
+    },
+    state::{self, Sections, StateError, StateWriter},
+};
+
+/// number of cycles taken to draw a single frame
+pub const FRAME_CYCLES: usize = 70224;
+
+/// state of every button on the joypad, `true` when held
+#[derive(Clone, Copy, Default)]
+pub struct Buttons {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
+/// entry point for frontends & tools embedding the emulator
+pub struct Emulator {
+    cpu: Cpu,
+}
+
+impl Emulator {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            cpu: Cpu::new(cart),
+        }
+    }
+
+    /// power cycle the machine with a different cartridge
+    ///
+    /// the boot rom, dmg palettes & serial link are kept, skipping the boot rom is up to the caller
+    pub fn load_rom(&mut self, cart: Cartridge) {
+        let mut cpu = Cpu::new(cart);
+        cpu.bus.take_config(&mut self.cpu.bus);
+
+        self.cpu = cpu;
+    }
+
+    /// colours used for dmg games, can be changed at any time
+    pub fn set_dmg_palettes(&mut self, palettes: DmgPalettes) {
+        self.cpu.bus.ppu.dmg_palettes = palettes;
+    }
+
+    /// replace the built-in boot rom, takes effect from power on
+    pub fn set_boot_rom(&mut self, rom: Vec<u8>) {
+        self.cpu.bus.set_boot_rom(rom);
+    }
+
+    /// jump straight to the cartridge entry point, must be called before running
+    pub fn skip_boot(&mut self) {
+        self.cpu.skip_boot();
+    }
+
+    /// run until the next frame has been drawn, returns the cycles taken
+    pub fn run_frame(&mut self) -> usize {
+        let frame = self.cpu.bus.ppu.frame;
+        let mut cycles = 0;
+
+        let budget = self.frame_cycles();
+
+        // a frame is never completed while the lcd is disabled
+        while frame == self.cpu.bus.ppu.frame && cycles < budget {
+            cycles += self.cpu.machine_cycle() as usize;
+        }
+
+        cycles
+    }
+
+    /// cpu cycles taken to draw a frame at the current speed
+    pub fn frame_cycles(&self) -> usize {
+        if self.cpu.bus.double_speed {
+            FRAME_CYCLES * 2
+        } else {
+            FRAME_CYCLES
+        }
+    }
+
+    /// execute a single instruction, returns the cycles taken
+    pub fn step_instruction(&mut self) -> u8 {
+        self.cpu.machine_cycle()
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        let joypad = &mut self.cpu.bus.joypad;
+        joypad.set_directions(buttons.right, buttons.left, buttons.up, buttons.down);
+        joypad.set_actions(buttons.a, buttons.b, buttons.select, buttons.start);
+    }
+
+    /// plug a device into the link port, replacing whatever was there
+    pub fn connect_serial(&mut self, link: Box<dyn SerialLink>) {
+        self.cpu.bus.serial.connect(link);
+    }
+
+    /// plug a capture into the link port, returns a handle to read what gets sent
+    pub fn capture_serial(&mut self) -> SerialCapture {
+        let capture = SerialCapture::new();
+        self.connect_serial(Box::new(capture.clone()));
+        capture
+    }
+
+    /// lcd output, `LCD_WIDTH` * `LCD_HEIGHT` pixels in row-major order
+    pub fn framebuffer(&self) -> &[Colour] {
+        &self.cpu.bus.ppu.framebuffer
+    }
+
+    /// number of frames drawn since power on
+    pub fn frame(&self) -> u32 {
+        self.cpu.bus.ppu.frame
+    }
+
+    /// take the samples produced since the last call, interleaved left & right
+    pub fn drain_audio(&mut self) -> Vec<i16> {
+        self.cpu.bus.apu.drain_samples()
+    }
+
+    /// snapshot of the whole machine, excluding the rom itself
+    pub fn save_state(&self) -> Vec<u8> {
+        let bus = &self.cpu.bus;
+        let mut w = StateWriter::default();
+
+        state::write_header(&mut w, &bus.cart.header);
+        w.section(b"CPU ", &self.cpu);
+        w.section(b"BUS ", bus);
+        w.section(b"PPU ", &bus.ppu);
+        w.section(b"APU ", &bus.apu);
+        w.section(b"TIMR", &bus.timer);
+        w.section(b"SERL", &bus.serial);
+        w.section(b"JOYP", &bus.joypad);
+        w.section(b"CART", &bus.cart);
+
+        w.into_inner()
+    }
+
+    /// restore a snapshot taken with `save_state`, the machine is untouched on error
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let sections = Sections::parse(data, &self.cpu.bus.cart.header)?;
+        let backup = self.save_state();
+
+        if let Err(e) = self.load_sections(&sections) {
+            let sections = Sections::parse(&backup, &self.cpu.bus.cart.header)?;
+            self.load_sections(&sections)?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// restore a snapshot this machine took itself, skipping the backup `load_state` makes
+    ///
+    /// only the header can mismatch, if the rom was swapped since, which is checked before
+    /// anything is loaded
+    pub(crate) fn load_own_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let sections = Sections::parse(data, &self.cpu.bus.cart.header)?;
+        self.load_sections(&sections)
+    }
+
+    fn load_sections(&mut self, sections: &Sections) -> Result<(), StateError> {
+        let bus = &mut self.cpu.bus;
+
+        sections.load(b"BUS ", bus)?;
+        sections.load(b"PPU ", &mut bus.ppu)?;
+        sections.load(b"APU ", &mut bus.apu)?;
+        sections.load(b"TIMR", &mut bus.timer)?;
+        sections.load(b"SERL", &mut bus.serial)?;
+        sections.load(b"JOYP", &mut bus.joypad)?;
+        sections.load(b"CART", &mut bus.cart)?;
+        sections.load(b"CPU ", &mut self.cpu)?;
+
+        Ok(())
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cpu.bus.cart
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cpu.bus.cart
+    }
+
+    /// direct access to the machine, for debuggers & test harnesses
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
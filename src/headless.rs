@@ -0,0 +1,171 @@
+use std::{error::Error, fmt, fs::File, io, io::BufWriter, path::Path};
+
+use crate::{
+    io::ppu::{Colour, LCD_WIDTH},
+    linked::LinkedPair,
+    Buttons, Emulator,
+};
+
+/// buttons to hold from a given frame onwards, read from lines of `<frame> <buttons>`
+///
+/// buttons are separated by commas, `-` releases everything & `#` starts a comment
+#[derive(Clone, Default)]
+pub struct InputScript {
+    /// sorted by frame
+    events: Vec<(u32, Buttons)>,
+}
+
+#[derive(Debug)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut events = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| ScriptError {
+                line: i + 1,
+                message,
+            };
+
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (frame, names) = line.split_once(char::is_whitespace).unwrap_or((line, "-"));
+
+            let frame = frame
+                .parse()
+                .map_err(|_| error(format!("invalid frame `{}`", frame)))?;
+
+            let mut buttons = Buttons::default();
+
+            for name in names.trim().split(',').map(str::trim) {
+                match name {
+                    "-" => {}
+                    "right" => buttons.right = true,
+                    "left" => buttons.left = true,
+                    "up" => buttons.up = true,
+                    "down" => buttons.down = true,
+                    "a" => buttons.a = true,
+                    "b" => buttons.b = true,
+                    "select" => buttons.select = true,
+                    "start" => buttons.start = true,
+                    _ => return Err(error(format!("unknown button `{}`", name))),
+                }
+            }
+
+            events.push((frame, buttons));
+        }
+
+        // stable, so later lines for the same frame still win
+        events.sort_by_key(|(frame, _)| *frame);
+
+        Ok(Self { events })
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+/// run a fixed number of frames, pressing buttons as the script says
+pub fn run(emulator: &mut Emulator, frames: u32, input: &InputScript) {
+    run_until(emulator, frames, input, |_| false);
+}
+
+/// like `run`, but stops once `done` returns true after a frame, returns how many frames
+/// that took or `None` if it never did
+pub fn run_until(
+    emulator: &mut Emulator,
+    frames: u32,
+    input: &InputScript,
+    mut done: impl FnMut(&Emulator) -> bool,
+) -> Option<u32> {
+    let mut events = input.events.iter().peekable();
+
+    for frame in 0..frames {
+        while let Some((_, buttons)) = events.next_if(|(at, _)| *at <= frame) {
+            emulator.set_buttons(*buttons);
+        }
+
+        emulator.run_frame();
+        // nobody is listening, stop samples piling up
+        emulator.drain_audio();
+
+        if done(emulator) {
+            return Some(frame + 1);
+        }
+    }
+
+    None
+}
+
+/// run both machines of a linked pair, each following its own script
+pub fn run_pair(pair: &mut LinkedPair, frames: u32, inputs: [&InputScript; 2]) {
+    let mut events = inputs.map(|input| input.events.iter().peekable());
+
+    for frame in 0..frames {
+        for (index, events) in events.iter_mut().enumerate() {
+            while let Some((_, buttons)) = events.next_if(|(at, _)| *at <= frame) {
+                pair.machine_mut(index).set_buttons(*buttons);
+            }
+        }
+
+        pair.run_frame();
+
+        for index in 0..2 {
+            pair.machine_mut(index).drain_audio();
+        }
+    }
+}
+
+/// fnv-1a over the rgb values, stable across runs & platforms
+pub fn framebuffer_hash(framebuffer: &[Colour]) -> u64 {
+    framebuffer
+        .iter()
+        .flat_map(|c| [c.r, c.g, c.b])
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+pub fn write_png(framebuffer: &[Colour], path: &Path) -> io::Result<()> {
+    encode_png(framebuffer, LCD_WIDTH, path)
+}
+
+/// both screens of a linked pair next to each other
+pub fn write_pair_png(first: &[Colour], second: &[Colour], path: &Path) -> io::Result<()> {
+    let framebuffer: Vec<Colour> = first
+        .chunks(LCD_WIDTH)
+        .zip(second.chunks(LCD_WIDTH))
+        .flat_map(|(left, right)| left.iter().chain(right))
+        .copied()
+        .collect();
+
+    encode_png(&framebuffer, LCD_WIDTH * 2, path)
+}
+
+fn encode_png(framebuffer: &[Colour], width: usize, path: &Path) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let height = framebuffer.len() / width;
+
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = framebuffer.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+
+    Ok(())
+}
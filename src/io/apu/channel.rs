@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 use super::{
     duty::Duty, envelope::Envelope, length::Length, noise::Noise, sweep::Sweep, wave::Wave,
 };
@@ -23,3 +25,33 @@ impl Channel {
         }
     }
 }
+
+impl Snapshot for Channel {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.dac);
+        w.bool(self.left);
+        w.bool(self.right);
+        self.length.save(w);
+        self.duty.save(w);
+        self.envelope.save(w);
+        self.sweep.save(w);
+        self.wave.save(w);
+        self.noise.save(w);
+        w.bool(self.enable);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.dac = r.bool()?;
+        self.left = r.bool()?;
+        self.right = r.bool()?;
+        self.length.load(r)?;
+        self.duty.load(r)?;
+        self.envelope.load(r)?;
+        self.sweep.load(r)?;
+        self.wave.load(r)?;
+        self.noise.load(r)?;
+        self.enable = r.bool()?;
+
+        Ok(())
+    }
+}
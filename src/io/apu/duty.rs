@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 use super::{timer::Timer, DUTY_TABLE};
 
 #[derive(Default)]
@@ -20,3 +22,25 @@ impl Duty {
         }
     }
 }
+
+impl Snapshot for Duty {
+    fn save(&self, w: &mut StateWriter) {
+        self.timer.save(w);
+        w.u8(self.pattern);
+        w.u8(self.position);
+        w.u16(self.frequency);
+        w.bool(self.state);
+        w.bool(self.enable);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer.load(r)?;
+        self.pattern = r.u8()?;
+        self.position = r.u8()?;
+        self.frequency = r.u16()?;
+        self.state = r.bool()?;
+        self.enable = r.bool()?;
+
+        Ok(())
+    }
+}
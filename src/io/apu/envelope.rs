@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 use super::timer::Timer;
 
 #[derive(Default)]
@@ -27,3 +29,23 @@ impl Envelope {
         }
     }
 }
+
+impl Snapshot for Envelope {
+    fn save(&self, w: &mut StateWriter) {
+        self.timer.save(w);
+        w.u8(self.start_volume);
+        w.u8(self.volume);
+        w.u8(self.direction as u8);
+        w.bool(self.enable);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer.load(r)?;
+        self.start_volume = r.u8()?;
+        self.volume = r.u8()?;
+        self.direction = r.u8()? as i8;
+        self.enable = r.bool()?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 use super::timer::Timer;
 
 #[derive(Default)]
@@ -5,3 +7,17 @@ pub struct Length {
     pub timer: Timer,
     pub enable: bool,
 }
+
+impl Snapshot for Length {
+    fn save(&self, w: &mut StateWriter) {
+        self.timer.save(w);
+        w.bool(self.enable);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer.load(r)?;
+        self.enable = r.bool()?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::cpu;
+use crate::{
+    cpu,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
 
 use self::channel::Channel;
 
@@ -20,6 +23,8 @@ const AMP_BASE: i16 = AMP_CHL / 16;
 pub const CHANNELS: usize = 2;
 pub const SAMPLE_RATE: usize = 48000;
 pub const LATENCY: usize = 16;
+/// samples kept when nobody is draining the buffer, one second of stereo audio
+const SAMPLE_BUFFER: usize = SAMPLE_RATE * CHANNELS;
 
 pub const DUTY_TABLE: [[bool; 8]; 4] = [
     [false, false, false, false, false, false, false, true],
@@ -82,6 +87,7 @@ pub struct Apu {
     pub output_left: i16,
     pub output_right: i16,
     pub update: bool,
+    samples: Vec<i16>,
 
     enable: bool,
 }
@@ -121,6 +127,11 @@ impl Apu {
                 self.ch3_sample();
                 self.ch4_sample();
 
+                if self.samples.len() < SAMPLE_BUFFER {
+                    self.samples.push(self.output_left);
+                    self.samples.push(self.output_right);
+                }
+
                 self.sample += 1;
                 self.update = true;
                 self.clock -= cycles_per_sample as u16;
@@ -133,6 +144,11 @@ impl Apu {
         }
     }
 
+    /// take the samples mixed since the last call, interleaved left & right
+    pub fn drain_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
     pub fn frame_sequencer(&mut self) {
         // length clock
         if self.frame_sequence & 1 == 0 {
@@ -401,3 +417,87 @@ impl Apu {
         }
     }
 }
+
+impl Snapshot for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.left_volume);
+        w.u8(self.right_volume);
+        w.u8(self.nr10);
+        w.u8(self.nr11);
+        w.u8(self.nr12);
+        w.u8(self.nr13);
+        w.u8(self.nr14);
+        w.u8(self.nr20);
+        w.u8(self.nr21);
+        w.u8(self.nr22);
+        w.u8(self.nr23);
+        w.u8(self.nr24);
+        w.u8(self.nr30);
+        w.u8(self.nr31);
+        w.u8(self.nr32);
+        w.u8(self.nr33);
+        w.u8(self.nr34);
+        w.u8(self.nr40);
+        w.u8(self.nr41);
+        w.u8(self.nr42);
+        w.u8(self.nr43);
+        w.u8(self.nr44);
+        w.u8(self.nr50);
+        w.u8(self.nr51);
+        w.u8(self.nr52);
+        self.ch1.save(w);
+        self.ch2.save(w);
+        self.ch3.save(w);
+        self.ch4.save(w);
+        w.bytes(&self.wave);
+        w.u16(self.clock);
+        w.u16(self.fs_clock);
+        w.u8(self.frame_sequence);
+        w.u64(self.sample as u64);
+        w.u16(self.output_left as u16);
+        w.u16(self.output_right as u16);
+        w.bool(self.enable);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.left_volume = r.u8()?;
+        self.right_volume = r.u8()?;
+        self.nr10 = r.u8()?;
+        self.nr11 = r.u8()?;
+        self.nr12 = r.u8()?;
+        self.nr13 = r.u8()?;
+        self.nr14 = r.u8()?;
+        self.nr20 = r.u8()?;
+        self.nr21 = r.u8()?;
+        self.nr22 = r.u8()?;
+        self.nr23 = r.u8()?;
+        self.nr24 = r.u8()?;
+        self.nr30 = r.u8()?;
+        self.nr31 = r.u8()?;
+        self.nr32 = r.u8()?;
+        self.nr33 = r.u8()?;
+        self.nr34 = r.u8()?;
+        self.nr40 = r.u8()?;
+        self.nr41 = r.u8()?;
+        self.nr42 = r.u8()?;
+        self.nr43 = r.u8()?;
+        self.nr44 = r.u8()?;
+        self.nr50 = r.u8()?;
+        self.nr51 = r.u8()?;
+        self.nr52 = r.u8()?;
+        self.ch1.load(r)?;
+        self.ch2.load(r)?;
+        self.ch3.load(r)?;
+        self.ch4.load(r)?;
+        r.bytes_into(&mut self.wave)?;
+        self.clock = r.u16()?;
+        self.fs_clock = r.u16()?;
+        self.frame_sequence = r.u8()?;
+        self.sample = r.u64()? as usize;
+        self.output_left = r.u16()? as i16;
+        self.output_right = r.u16()? as i16;
+        self.enable = r.bool()?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 use super::timer::Timer;
 
 #[derive(Default)]
@@ -25,3 +27,23 @@ impl Noise {
         }
     }
 }
+
+impl Snapshot for Noise {
+    fn save(&self, w: &mut StateWriter) {
+        self.timer.save(w);
+        w.u8(self.shift);
+        w.bool(self.width_mode);
+        w.u16(self.lfsr);
+        w.bool(self.state);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer.load(r)?;
+        self.shift = r.u8()?;
+        self.width_mode = r.bool()?;
+        self.lfsr = r.u16()?;
+        self.state = r.bool()?;
+
+        Ok(())
+    }
+}
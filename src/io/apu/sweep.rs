@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 use super::{channel::Channel, duty::Duty, timer::Timer};
 
 #[derive(Default)]
@@ -35,3 +37,25 @@ impl Sweep {
         }
     }
 }
+
+impl Snapshot for Sweep {
+    fn save(&self, w: &mut StateWriter) {
+        self.timer.save(w);
+        w.u16(self.frequency);
+        w.u8(self.shift);
+        w.bool(self.decreasing);
+        w.bool(self.calculated);
+        w.bool(self.enable);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer.load(r)?;
+        self.frequency = r.u16()?;
+        self.shift = r.u8()?;
+        self.decreasing = r.bool()?;
+        self.calculated = r.bool()?;
+        self.enable = r.bool()?;
+
+        Ok(())
+    }
+}
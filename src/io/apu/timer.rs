@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 #[derive(Default)]
 pub struct Timer {
     pub counter: u16,
@@ -26,3 +28,17 @@ impl Timer {
         self.counter = self.period;
     }
 }
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.u16(self.period);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.u16()?;
+        self.period = r.u16()?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::io::{map, Bus};
+use crate::{
+    io::{map, Bus},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
 
 use super::timer::Timer;
 
@@ -24,3 +27,23 @@ impl Wave {
         }
     }
 }
+
+impl Snapshot for Wave {
+    fn save(&self, w: &mut StateWriter) {
+        self.timer.save(w);
+        w.u16(self.frequency);
+        w.u8(self.shift);
+        w.u16(self.position);
+        w.u8(self.output);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer.load(r)?;
+        self.frequency = r.u16()?;
+        self.shift = r.u8()?;
+        self.position = r.u16()?;
+        self.output = r.u8()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use std::{error::Error, fmt, io};
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// unable to read the rom or save file
+    Io(io::Error),
+    /// rom is too small to contain a header & two banks
+    TooSmall(usize),
+    /// cartridge type uses a controller that isn't emulated
+    UnsupportedMbc(u8),
+    /// header checksum doesn't match, the boot rom would lock up
+    BadHeaderChecksum { expected: u8, computed: u8 },
+    /// rom is smaller than the size declared in the header
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(e) => write!(f, "{}", e),
+            CartridgeError::TooSmall(size) => {
+                write!(f, "rom is too small ({} bytes)", size)
+            }
+            CartridgeError::UnsupportedMbc(id) => {
+                write!(f, "unsupported cartridge type `0x{:02x}`", id)
+            }
+            CartridgeError::BadHeaderChecksum { expected, computed } => write!(
+                f,
+                "bad header checksum, expected `0x{:02x}` but computed `0x{:02x}`",
+                expected, computed
+            ),
+            CartridgeError::SizeMismatch { expected, actual } => write!(
+                f,
+                "header declares {} bytes of rom but only {} were found",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CartridgeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt;
+
+use super::{error::CartridgeError, mbc::MbcType, offsets, sizes};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CgbFlag {
+    /// original gameboy only
+    Dmg,
+    /// enhanced for the gameboy color, but still runs on the original
+    Supported,
+    /// gameboy color only
+    Only,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Licensee {
+    /// single byte code used by older cartridges
+    Old(u8),
+    /// two character code used when the old code is 0x33
+    New(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CartridgeType {
+    /// raw cartridge type byte
+    pub id: u8,
+    /// memory bank controller, `None` if the controller isn't emulated
+    pub mbc: Option<MbcType>,
+    pub ram: bool,
+    pub battery: bool,
+    pub rtc: bool,
+    pub rumble: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// title area as stored, including any manufacturer code & the cgb flag
+    pub title_bytes: [u8; 16],
+    /// four character manufacturer code found on later cartridges
+    pub manufacturer: Option<String>,
+    pub cgb: CgbFlag,
+    pub sgb: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: CartridgeType,
+    /// size of rom in bytes
+    pub rom_size: usize,
+    pub rom_banks: u16,
+    /// size of external ram in bytes, including ram built into the controller
+    pub ram_size: usize,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    /// checksums calculated over the rom
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeType {
+    pub fn parse(id: u8) -> Self {
+        let (mbc, ram, battery, rtc, rumble) = match id {
+            0x00 => (Some(MbcType::RomOnly), false, false, false, false),
+            0x01 => (Some(MbcType::Mbc1), false, false, false, false),
+            0x02 => (Some(MbcType::Mbc1), true, false, false, false),
+            0x03 => (Some(MbcType::Mbc1), true, true, false, false),
+            0x05 => (Some(MbcType::Mbc2), true, false, false, false),
+            0x06 => (Some(MbcType::Mbc2), true, true, false, false),
+            0x08 => (Some(MbcType::RomOnly), true, false, false, false),
+            0x09 => (Some(MbcType::RomOnly), true, true, false, false),
+            0x0b => (None, false, false, false, false),
+            0x0c => (None, true, false, false, false),
+            0x0d => (None, true, true, false, false),
+            0x0f => (Some(MbcType::Mbc3), false, true, true, false),
+            0x10 => (Some(MbcType::Mbc3), true, true, true, false),
+            0x11 => (Some(MbcType::Mbc3), false, false, false, false),
+            0x12 => (Some(MbcType::Mbc3), true, false, false, false),
+            0x13 => (Some(MbcType::Mbc3), true, true, false, false),
+            0x19 => (Some(MbcType::Mbc5), false, false, false, false),
+            0x1a => (Some(MbcType::Mbc5), true, false, false, false),
+            0x1b => (Some(MbcType::Mbc5), true, true, false, false),
+            0x1c => (Some(MbcType::Mbc5), false, false, false, true),
+            0x1d => (Some(MbcType::Mbc5), true, false, false, true),
+            0x1e => (Some(MbcType::Mbc5), true, true, false, true),
+            0x22 => (None, true, true, false, true),
+            0xff => (None, true, true, false, false),
+            _ => (None, false, false, false, false),
+        };
+
+        Self {
+            id,
+            mbc,
+            ram,
+            battery,
+            rtc,
+            rumble,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        // ensure there are at least two rom banks
+        if rom.len() < sizes::ROM_BANK * 2 {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb = match rom[offsets::CGB_FLAG] {
+            0xc0 => CgbFlag::Only,
+            0x80 => CgbFlag::Supported,
+            _ => CgbFlag::Dmg,
+        };
+
+        // later cartridges shortened the title to make room for the manufacturer & cgb flag,
+        // but plenty of cgb titles still run right up to the flag
+        let (title, manufacturer) = if cgb == CgbFlag::Dmg {
+            (&rom[offsets::TITLE..offsets::CGB_FLAG + 1], None)
+        } else {
+            let manufacturer = &rom[offsets::MANUFACTURER..offsets::CGB_FLAG];
+
+            if manufacturer.iter().all(u8::is_ascii_alphanumeric) {
+                (
+                    &rom[offsets::TITLE..offsets::MANUFACTURER],
+                    Some(ascii_string(manufacturer)),
+                )
+            } else {
+                (&rom[offsets::TITLE..offsets::CGB_FLAG], None)
+            }
+        };
+
+        let licensee = match rom[offsets::OLD_LICENSEE] {
+            0x33 => Licensee::New(ascii_string(
+                &rom[offsets::NEW_LICENSEE..offsets::NEW_LICENSEE + 2],
+            )),
+            code => Licensee::Old(code),
+        };
+
+        let cartridge_type = CartridgeType::parse(rom[offsets::TYPE]);
+
+        // each increment doubles the size, starting at two banks
+        let rom_banks = match rom[offsets::ROM_SIZE] {
+            code @ 0..=8 => 2 << code,
+            _ => (rom.len() / sizes::ROM_BANK).max(2) as u16,
+        };
+
+        let ram_size = match cartridge_type.mbc {
+            Some(MbcType::Mbc2) => sizes::MBC2_RAM,
+            _ => match rom[offsets::RAM_SIZE] {
+                1 => sizes::RAM_BANK / 4,
+                2 => sizes::RAM_BANK,
+                3 => sizes::RAM_BANK * 4,
+                4 => sizes::RAM_BANK * 16,
+                5 => sizes::RAM_BANK * 8,
+                _ => 0,
+            },
+        };
+
+        Ok(Self {
+            title: ascii_string(title),
+            title_bytes: rom[offsets::TITLE..offsets::NEW_LICENSEE]
+                .try_into()
+                .unwrap(),
+            manufacturer,
+            cgb,
+            sgb: rom[offsets::SGB_FLAG] == 0x03 && rom[offsets::OLD_LICENSEE] == 0x33,
+            licensee,
+            cartridge_type,
+            rom_size: rom_banks as usize * sizes::ROM_BANK,
+            rom_banks,
+            ram_size,
+            version: rom[offsets::VERSION],
+            header_checksum: rom[offsets::HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([
+                rom[offsets::GLOBAL_CHECKSUM],
+                rom[offsets::GLOBAL_CHECKSUM + 1],
+            ]),
+            computed_header_checksum: Self::compute_header_checksum(rom),
+            computed_global_checksum: Self::compute_global_checksum(rom),
+        })
+    }
+
+    /// checksum over the title through to the version, checked by the boot rom
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[offsets::TITLE..offsets::HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |checksum, byte| {
+                checksum.wrapping_sub(*byte).wrapping_sub(1)
+            })
+    }
+
+    /// sum of every byte in the rom except the global checksum itself
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| !(offsets::GLOBAL_CHECKSUM..=offsets::GLOBAL_CHECKSUM + 1).contains(i))
+            .fold(0u16, |checksum, (_, byte)| {
+                checksum.wrapping_add(*byte as u16)
+            })
+    }
+
+    pub fn verify_header_checksum(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// real hardware never checks this, so plenty of working roms fail it
+    pub fn verify_global_checksum(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+}
+
+impl fmt::Display for CgbFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CgbFlag::Dmg => write!(f, "dmg"),
+            CgbFlag::Supported => write!(f, "cgb supported"),
+            CgbFlag::Only => write!(f, "cgb only"),
+        }
+    }
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Licensee::Old(code) => write!(f, "{:02x}", code),
+            Licensee::New(code) => write!(f, "{}", code),
+        }
+    }
+}
+
+/// printable ascii up to the first null byte
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+        .map(|byte| *byte as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
@@ -1,10 +1,15 @@
 use log::warn;
 
-use super::sizes;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
+use super::{header::CartridgeHeader, offsets, rtc::Rtc, sizes};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum MbcType {
     RomOnly,
     Mbc1,
+    Mbc2,
+    Mbc3,
     Mbc5,
 }
 
@@ -14,64 +19,294 @@ pub struct Mbc {
     /// catridge random access memory
     ram: Vec<u8>,
     /// number of banks within the cartridge (see controllers)
-    rom_banks: u8,
+    rom_banks: u16,
     /// currently selected rom bank
-    rom_bank: u8,
+    rom_bank: u16,
+    /// rom bank mapped into 0x0000-0x3fff
+    zero_bank: u16,
     /// currently selected ram bank
     ram_bank: u8,
+    /// external ram is enabled for reading & writing
+    ram_enable: bool,
+    /// external ram or clock has been written since the last save
+    dirty: bool,
+    /// ram was disabled after being written, a good time to save
+    flush: bool,
+    /// mbc1 lower 5-bit bank register
+    bank_low: u8,
+    /// mbc1 upper 2-bit bank register
+    bank_high: u8,
+    /// mbc1 banking mode, upper bits apply to 0x0000-0x3fff & ram when set
+    banking_mode: bool,
+    /// mbc1m multicart, lower bank register is wired as 4 bits
+    multicart: bool,
+    /// mbc3 real time clock
+    pub rtc: Rtc,
+    /// mbc3 rtc register mapped into external ram, if any
+    rtc_select: Option<u8>,
+    /// mbc5 cartridge has a rumble motor wired to the ram bank register
+    has_rumble: bool,
+    /// rumble motor is currently spinning
+    rumble: bool,
     /// type of controller
     mbc_type: MbcType,
 }
 
 impl Mbc {
-    pub fn new(rom: Vec<u8>, id: u8) -> Self {
-        let mbc_type = match id {
-            0 => MbcType::RomOnly,
-            1..=3 => MbcType::Mbc1,
-            _ => MbcType::Mbc5,
-        };
+    pub fn new(rom: Vec<u8>, mbc_type: MbcType, header: &CartridgeHeader) -> Self {
+        let multicart = mbc_type == MbcType::Mbc1 && Self::detect_multicart(&rom);
 
         Self {
             rom,
-            ram: Vec::from([0; sizes::RAM_BANK * sizes::RAM_COUNT]),
-            rom_banks: 2,
+            ram: vec![0; header.ram_size],
+            rom_banks: header.rom_banks,
             rom_bank: 1,
+            zero_bank: 0,
             ram_bank: 0,
+            ram_enable: false,
+            dirty: false,
+            flush: false,
+            bank_low: 1,
+            bank_high: 0,
+            banking_mode: false,
+            multicart,
+            rtc: Rtc::new(),
+            rtc_select: None,
+            has_rumble: header.cartridge_type.rumble,
+            rumble: false,
             mbc_type,
         }
     }
 
+    /// mbc1m carts are 1MiB and repeat the nintendo logo at the start of each 256KiB game
+    fn detect_multicart(rom: &[u8]) -> bool {
+        if rom.len() != sizes::ROM_BANK * 64 {
+            return false;
+        }
+
+        let logo = &rom[offsets::LOGO..offsets::LOGO + sizes::LOGO];
+        let game = sizes::ROM_BANK * 0x10 + offsets::LOGO;
+
+        logo == &rom[game..game + sizes::LOGO]
+    }
+
     pub fn fetch_rom_byte(&self, address: u16) -> u8 {
         // check if the address is within the first bank
         if address < sizes::ROM_BANK as u16 {
-            return self.rom[address as usize];
+            return self.rom_byte(self.zero_bank, address as usize);
         }
 
         // otherwise return the switchable bank
-        self.rom[(address as usize) - sizes::ROM_BANK + (self.rom_bank as usize * sizes::ROM_BANK)]
+        self.rom_byte(self.rom_bank, address as usize - sizes::ROM_BANK)
+    }
+
+    fn rom_byte(&self, bank: u16, offset: usize) -> u8 {
+        // banks beyond the size of the rom wrap around
+        let bank = (bank % self.rom_banks) as usize;
+        self.rom
+            .get(bank * sizes::ROM_BANK + offset)
+            .copied()
+            .unwrap_or(0xff)
     }
 
     pub fn store_rom_byte(&mut self, address: u16, value: u8) {
         match self.mbc_type {
             MbcType::RomOnly => warn!("unhandled rom write: {:04x} = {:02x}", address, value),
-            MbcType::Mbc1 => match address {
-                0x2000..=0x3fff => {
-                    let curr_bank = self.rom_bank & 0xe0;
-                    let bank = curr_bank | (value & 0x1f);
+            MbcType::Mbc1 => {
+                match address {
+                    0x0000..=0x1fff => self.set_ram_enable(value),
+                    0x2000..=0x3fff => {
+                        // bank 0 can't be selected, it maps to bank 1 instead
+                        self.bank_low = (value & 0x1f).max(1);
+                    }
+                    0x4000..=0x5fff => self.bank_high = value & 3,
+                    0x6000..=0x7fff => self.banking_mode = value & 1 != 0,
+                    _ => unreachable!(),
+                }
 
-                    self.rom_bank = bank;
+                self.update_mbc1_banks();
+            }
+            MbcType::Mbc2 => match address {
+                // address bit 8 selects between the ram enable & rom bank registers
+                0x0000..=0x3fff if address & 0x100 == 0 => self.set_ram_enable(value),
+                0x0000..=0x3fff => self.rom_bank = (value as u16 & 0xf).max(1),
+                _ => warn!("unhandled rom write: {:04x} = {:02x}", address, value),
+            },
+            MbcType::Mbc3 => match address {
+                0x0000..=0x1fff => self.set_ram_enable(value),
+                0x2000..=0x3fff => self.rom_bank = (value as u16 & 0x7f).max(1),
+                0x4000..=0x5fff => match value {
+                    0x00..=0x07 => {
+                        self.ram_bank = value;
+                        self.rtc_select = None;
+                    }
+                    0x08..=0x0c => self.rtc_select = Some(value),
+                    _ => warn!("unhandled rom write: {:04x} = {:02x}", address, value),
+                },
+                0x6000..=0x7fff => self.rtc.write_latch(value),
+                _ => unreachable!(),
+            },
+            MbcType::Mbc5 => match address {
+                0x0000..=0x1fff => self.set_ram_enable(value),
+                // unlike other controllers, bank 0 can be mapped into the switchable area
+                0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+                0x3000..=0x3fff => {
+                    self.rom_bank = (self.rom_bank & 0xff) | ((value as u16 & 1) << 8)
+                }
+                0x4000..=0x5fff => {
+                    if self.has_rumble {
+                        self.rumble = value & 0x08 != 0;
+                        self.ram_bank = value & 0x07;
+                    } else {
+                        self.ram_bank = value & 0x0f;
+                    }
                 }
                 _ => warn!("unhandled rom write: {:04x} = {:02x}", address, value),
             },
-            MbcType::Mbc5 => warn!("unhandled rom write: {:04x} = {:02x}", address, value),
         }
     }
 
+    fn set_ram_enable(&mut self, value: u8) {
+        let enable = value & 0xf == 0xa;
+
+        // games disable ram once they are done saving
+        if self.ram_enable && !enable && self.dirty {
+            self.flush = true;
+        }
+
+        self.ram_enable = enable;
+    }
+
+    /// returns whether external ram should be written out, clearing the request
+    pub fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
+    }
+
+    /// returns whether external ram has changed since it was last saved
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// external ram has been saved, along with any pending request to save it
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+        self.flush = false;
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn update_mbc1_banks(&mut self) {
+        // multicarts don't connect the top bit of the lower bank register
+        let (low, shift) = if self.multicart {
+            (self.bank_low & 0xf, 4)
+        } else {
+            (self.bank_low, 5)
+        };
+
+        let high = (self.bank_high as u16) << shift;
+
+        self.rom_bank = high | low as u16;
+
+        if self.banking_mode {
+            self.zero_bank = high;
+            self.ram_bank = self.bank_high;
+        } else {
+            self.zero_bank = 0;
+            self.ram_bank = 0;
+        }
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    pub fn tick(&mut self) {
+        if let MbcType::Mbc3 = self.mbc_type {
+            self.rtc.tick();
+        }
+    }
+
+    /// returns `None` if the cartridge has no external ram
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        // banks & addresses beyond the size of the ram wrap around
+        let address = address as usize + self.ram_bank as usize * sizes::RAM_BANK;
+        address.checked_rem(self.ram.len())
+    }
+
     pub fn fetch_ram_byte(&self, address: u16) -> u8 {
-        self.ram[address as usize + (self.ram_bank as usize * sizes::RAM_BANK)]
+        match (&self.mbc_type, self.rtc_select) {
+            (MbcType::RomOnly, _) => self
+                .ram_address(address)
+                .map_or(0xff, |address| self.ram[address]),
+            _ if !self.ram_enable => 0xff,
+            // built-in ram is only 4 bits wide & echoes throughout the address space
+            (MbcType::Mbc2, _) => 0xf0 | self.ram[address as usize % sizes::MBC2_RAM],
+            (MbcType::Mbc3, Some(register)) => self.rtc.read(register),
+            _ => self
+                .ram_address(address)
+                .map_or(0xff, |address| self.ram[address]),
+        }
     }
 
     pub fn store_ram_byte(&mut self, address: u16, value: u8) {
-        self.ram[address as usize + (self.ram_bank as usize * sizes::RAM_BANK)] = value;
+        self.dirty |= self.ram_enable || matches!(self.mbc_type, MbcType::RomOnly);
+
+        match (&self.mbc_type, self.rtc_select) {
+            (MbcType::RomOnly, _) => {
+                if let Some(address) = self.ram_address(address) {
+                    self.ram[address] = value;
+                }
+            }
+            _ if !self.ram_enable => {}
+            (MbcType::Mbc2, _) => self.ram[address as usize % sizes::MBC2_RAM] = value & 0xf,
+            (MbcType::Mbc3, Some(register)) => self.rtc.write(register, value),
+            _ => {
+                if let Some(address) = self.ram_address(address) {
+                    self.ram[address] = value;
+                }
+            }
+        }
+    }
+}
+
+impl Snapshot for Mbc {
+    fn save(&self, w: &mut StateWriter) {
+        w.sized_bytes(&self.ram);
+        w.u16(self.rom_bank);
+        w.u16(self.zero_bank);
+        w.u8(self.ram_bank);
+        w.bool(self.ram_enable);
+        w.u8(self.bank_low);
+        w.u8(self.bank_high);
+        w.bool(self.banking_mode);
+        Snapshot::save(&self.rtc, w);
+        w.u8(self.rtc_select.unwrap_or(0));
+        w.bool(self.rumble);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.sized_bytes_into(&mut self.ram)?;
+        self.rom_bank = r.u16()?;
+        self.zero_bank = r.u16()?;
+        self.ram_bank = r.u8()?;
+        self.ram_enable = r.bool()?;
+        self.bank_low = r.u8()?;
+        self.bank_high = r.u8()?;
+        self.banking_mode = r.bool()?;
+        Snapshot::load(&mut self.rtc, r)?;
+        self.rtc_select = Some(r.u8()?).filter(|select| *select != 0);
+        self.rumble = r.bool()?;
+
+        // the loaded ram needs to reach the save file too
+        self.dirty = true;
+
+        Ok(())
     }
 }
@@ -1,57 +1,267 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{Read, Result as IoResult},
-    path::Path,
+    fs,
+    io::{ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
 };
 
-use self::mbc::Mbc;
+use log::{error, warn};
 
+use crate::{
+    cpu,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+pub use self::{
+    error::CartridgeError,
+    header::{CartridgeHeader, CartridgeType, CgbFlag, Licensee},
+    mbc::{Mbc, MbcType},
+    rtc::{Rtc, RtcRegisters},
+};
+
+mod error;
+mod header;
 mod mbc;
+mod rtc;
 
 mod sizes {
     /// each rom bank is 16KiB
     pub const ROM_BANK: usize = 1024 * 16;
     /// each ram bank is 8KiB
     pub const RAM_BANK: usize = 1024 * 8;
-    /// number of ram banks in mb5
-    pub const RAM_COUNT: usize = 16;
+    /// mbc2 has 512 half-bytes of built-in ram
+    pub const MBC2_RAM: usize = 512;
+    /// nintendo logo is 48 bytes
+    pub const LOGO: usize = 0x30;
 }
 
+/// how often dirty external ram is written to disk, in seconds of emulated time
+const FLUSH_INTERVAL: usize = 5;
+
 mod offsets {
+    pub const LOGO: usize = 0x104;
     pub const TITLE: usize = 0x134;
+    pub const MANUFACTURER: usize = 0x13f;
+    pub const CGB_FLAG: usize = 0x143;
+    pub const NEW_LICENSEE: usize = 0x144;
+    pub const SGB_FLAG: usize = 0x146;
     pub const TYPE: usize = 0x147;
     pub const ROM_SIZE: usize = 0x148;
     pub const RAM_SIZE: usize = 0x149;
+    pub const OLD_LICENSEE: usize = 0x14b;
+    pub const VERSION: usize = 0x14c;
+    pub const HEADER_CHECKSUM: usize = 0x14d;
+    pub const GLOBAL_CHECKSUM: usize = 0x14e;
 }
 
 pub struct Cartridge {
     /// memory bank controller (mbc)
     pub controller: Mbc,
+    /// parsed cartridge header
+    pub header: CartridgeHeader,
     /// file to save external ram to
-    save_file: File,
+    save_path: Option<PathBuf>,
+    /// cycles since dirty external ram was last checked
+    flush_ticks: usize,
 }
 
 impl Cartridge {
-    pub fn from_path(path: &Path) -> IoResult<Cartridge> {
-        // open rom file
-        let mut cart_file = File::open(path)?;
+    /// load a rom file, along with its save file if the cartridge has a battery
+    pub fn from_path(path: &Path) -> Result<Cartridge, CartridgeError> {
+        let mut cart = Self::from_bytes(fs::read(path)?)?;
+        cart.attach_save(path.with_extension("sav"))?;
+
+        Ok(cart)
+    }
+
+    /// load a rom from memory, external ram won't be saved until a save file is attached
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
 
-        // read contents of rom into vector
-        let mut rom = Vec::new();
-        cart_file.read_to_end(&mut rom)?;
+        let mbc_type = header
+            .cartridge_type
+            .mbc
+            .ok_or(CartridgeError::UnsupportedMbc(header.cartridge_type.id))?;
 
-        // ensure there are at least two rom banks
-        assert!(rom.len() >= sizes::ROM_BANK * 2);
+        if !header.verify_header_checksum() {
+            return Err(CartridgeError::BadHeaderChecksum {
+                expected: header.header_checksum,
+                computed: CartridgeHeader::compute_header_checksum(&rom),
+            });
+        }
 
-        let mbc_type = rom[offsets::TYPE];
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::SizeMismatch {
+                expected: header.rom_size,
+                actual: rom.len(),
+            });
+        } else if rom.len() > header.rom_size {
+            warn!(
+                "rom is larger than the {} bytes declared in the header",
+                header.rom_size
+            );
+        }
 
         Ok(Self {
-            controller: Mbc::new(rom, mbc_type),
-            save_file: OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(path.with_extension("sav"))
-                .expect("unable to write save file"),
+            controller: Mbc::new(rom, mbc_type, &header),
+            header,
+            save_path: None,
+            flush_ticks: 0,
         })
     }
+
+    /// load external ram from a save file, which is also where it will be saved
+    pub fn attach_save(&mut self, path: PathBuf) -> IoResult<()> {
+        if !self.header.cartridge_type.battery {
+            return Ok(());
+        }
+
+        match fs::read(&path) {
+            Ok(data) => {
+                let ram_size = self.header.ram_size.min(data.len());
+                self.controller.load_ram(&data[..ram_size]);
+
+                if self.header.cartridge_type.rtc {
+                    self.controller.rtc.load(&data[ram_size..]);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        self.save_path = Some(path);
+
+        Ok(())
+    }
+
+    /// write external ram, followed by the rtc if present, to the save file
+    pub fn save(&mut self) -> IoResult<()> {
+        let Some(path) = &self.save_path else {
+            return Ok(());
+        };
+
+        let mut data = self.controller.ram().to_vec();
+
+        if self.header.cartridge_type.rtc {
+            data.extend(self.controller.rtc.save());
+        }
+
+        fs::write(path, data)
+    }
+
+    /// save only if external ram has changed since the last save
+    ///
+    /// ram stays dirty if the write fails, so the next flush tries again
+    pub fn flush(&mut self) -> IoResult<()> {
+        if self.controller.dirty() {
+            self.save()?;
+            self.controller.clear_dirty();
+        }
+
+        Ok(())
+    }
+
+    pub fn tick(&mut self) {
+        self.controller.tick();
+        self.flush_ticks += 1;
+
+        let periodic = self.flush_ticks >= cpu::FREQUENCY * FLUSH_INTERVAL;
+
+        if periodic || self.controller.take_flush() {
+            self.flush_ticks = 0;
+
+            if let Err(e) = self.flush() {
+                error!("unable to write save file: {}", e);
+            }
+        }
+    }
+
+    /// state of the rumble motor, for frontends that can vibrate
+    pub fn rumble(&self) -> bool {
+        self.controller.rumble()
+    }
+
+    /// drive the rtc from host time rather than emulated cycles
+    pub fn set_rtc_wall_clock(&mut self, enable: bool) {
+        self.controller.rtc.set_wall_clock(enable);
+    }
+}
+
+impl Snapshot for Cartridge {
+    fn save(&self, w: &mut StateWriter) {
+        self.controller.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.controller.load(r)
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("unable to write save file: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32KiB mbc1 cartridge with 8KiB of battery backed ram
+    fn battery_cart() -> Cartridge {
+        let mut rom = vec![0; sizes::ROM_BANK * 2];
+        rom[offsets::TYPE] = 0x03;
+        rom[offsets::RAM_SIZE] = 0x02;
+        rom[offsets::HEADER_CHECKSUM] = CartridgeHeader::compute_header_checksum(&rom);
+
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gameboy-{}-{}.sav", name, std::process::id()))
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let path = temp_path("round-trip");
+        let _ = fs::remove_file(&path);
+
+        let mut cart = battery_cart();
+        cart.attach_save(path.clone()).unwrap();
+
+        cart.controller.store_rom_byte(0x0000, 0x0a);
+        cart.controller.store_ram_byte(0x0123, 0x42);
+        cart.controller.store_rom_byte(0x0000, 0x00);
+        assert!(cart.controller.take_flush());
+
+        cart.flush().unwrap();
+        assert!(!cart.controller.dirty());
+        drop(cart);
+
+        let mut cart = battery_cart();
+        cart.attach_save(path.clone()).unwrap();
+        cart.controller.store_rom_byte(0x0000, 0x0a);
+
+        assert_eq!(cart.controller.fetch_ram_byte(0x0123), 0x42);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_save_stays_dirty() {
+        let path = temp_path("missing").join("game.sav");
+
+        let mut cart = battery_cart();
+        cart.attach_save(path).unwrap();
+
+        cart.controller.store_rom_byte(0x0000, 0x0a);
+        cart.controller.store_ram_byte(0x0000, 0x42);
+
+        assert!(cart.flush().is_err());
+        assert!(cart.controller.dirty());
+
+        // nothing to retry into, don't let drop log the same failure
+        cart.controller.clear_dirty();
+    }
 }
@@ -0,0 +1,327 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    cpu,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+/// values written to the ram bank register to map an rtc register
+pub mod select {
+    pub const SECONDS: u8 = 0x08;
+    pub const MINUTES: u8 = 0x09;
+    pub const HOURS: u8 = 0x0a;
+    pub const DAY_LOW: u8 = 0x0b;
+    pub const DAY_HIGH: u8 = 0x0c;
+}
+
+/// size of the rtc block appended to save files, timestamp may be 32 or 64 bits
+pub const SAVE_SIZE: usize = 48;
+pub const SAVE_SIZE_SHORT: usize = 44;
+
+#[derive(Clone, Copy, Default)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    /// 9-bit day counter
+    pub days: u16,
+    /// clock is stopped
+    pub halt: bool,
+    /// day counter has overflowed
+    pub carry: bool,
+}
+
+impl RtcRegisters {
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    /// advance by a single second, out of range values wrap without carrying
+    fn step(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1ff {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    pub fn day_high(&self) -> u8 {
+        ((self.days >> 8) as u8 & 1) | (self.halt as u8) << 6 | (self.carry as u8) << 7
+    }
+
+    pub fn set_day_high(&mut self, value: u8) {
+        self.days = (self.days & 0xff) | ((value as u16 & 1) << 8);
+        self.halt = value & 0x40 != 0;
+        self.carry = value & 0x80 != 0;
+    }
+
+    fn save(&self, data: &mut Vec<u8>) {
+        let registers = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            self.day_high(),
+        ];
+
+        for register in registers {
+            data.extend_from_slice(&(register as u32).to_le_bytes());
+        }
+    }
+
+    fn load(data: &[u8]) -> Self {
+        let register = |i: usize| data[i * 4];
+
+        let mut registers = Self {
+            seconds: register(0) & 0x3f,
+            minutes: register(1) & 0x3f,
+            hours: register(2) & 0x1f,
+            days: register(3) as u16,
+            ..Default::default()
+        };
+
+        registers.set_day_high(register(4));
+        registers
+    }
+}
+
+pub struct Rtc {
+    /// live clock registers
+    pub clock: RtcRegisters,
+    /// copy of the clock visible to the cartridge, updated on latch
+    pub latched: RtcRegisters,
+    /// last value written to the latch register
+    latch: u8,
+    /// cycles elapsed since the last second
+    cycles: u32,
+    /// follow host wall time instead of emulated cycles
+    wall_clock: bool,
+    /// host time of the last sync, in seconds
+    synced_at: u64,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            clock: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            latch: 0xff,
+            cycles: 0,
+            wall_clock: false,
+            synced_at: host_time(),
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.wall_clock || self.clock.halt {
+            return;
+        }
+
+        self.cycles += 1;
+
+        if self.cycles >= cpu::FREQUENCY as u32 {
+            self.cycles = 0;
+            self.clock.step();
+        }
+    }
+
+    pub fn set_wall_clock(&mut self, enable: bool) {
+        self.wall_clock = enable;
+        self.synced_at = host_time();
+    }
+
+    /// catch the clock up with host time
+    pub fn sync(&mut self) {
+        let now = host_time();
+
+        if self.wall_clock {
+            self.advance(now.saturating_sub(self.synced_at));
+        }
+
+        self.synced_at = now;
+    }
+
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.clock.halt {
+            return;
+        }
+
+        // step through out of range values one second at a time
+        while seconds > 0 && !self.clock.in_range() {
+            self.clock.step();
+            seconds -= 1;
+        }
+
+        if seconds == 0 {
+            return;
+        }
+
+        let total = self.clock.seconds as u64
+            + self.clock.minutes as u64 * 60
+            + self.clock.hours as u64 * 3600
+            + self.clock.days as u64 * 86400
+            + seconds;
+
+        let days = total / 86400;
+
+        self.clock.seconds = (total % 60) as u8;
+        self.clock.minutes = (total / 60 % 60) as u8;
+        self.clock.hours = (total / 3600 % 24) as u8;
+        self.clock.days = (days & 0x1ff) as u16;
+
+        if days > 0x1ff {
+            self.clock.carry = true;
+        }
+    }
+
+    /// serialise as the 48 byte block used by other emulators
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SAVE_SIZE);
+
+        self.clock.save(&mut data);
+        self.latched.save(&mut data);
+
+        // a wall clock is only as current as its last sync, the rest is caught up on load
+        let saved_at = if self.wall_clock {
+            self.synced_at
+        } else {
+            host_time()
+        };
+        data.extend_from_slice(&saved_at.to_le_bytes());
+
+        data
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < SAVE_SIZE_SHORT {
+            return;
+        }
+
+        self.clock = RtcRegisters::load(&data[..20]);
+        self.latched = RtcRegisters::load(&data[20..40]);
+
+        let saved_at = if data.len() >= SAVE_SIZE {
+            u64::from_le_bytes(data[40..48].try_into().unwrap_or_default())
+        } else {
+            u32::from_le_bytes(data[40..44].try_into().unwrap_or_default()) as u64
+        };
+
+        // the battery kept the clock running while the emulator was closed
+        self.synced_at = host_time();
+        self.advance(self.synced_at.saturating_sub(saved_at));
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        // writing 0x00 then 0x01 copies the clock into the latched registers
+        if self.latch == 0 && value == 1 {
+            self.sync();
+            self.latched = self.clock;
+        }
+
+        self.latch = value;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            select::SECONDS => self.latched.seconds,
+            select::MINUTES => self.latched.minutes,
+            select::HOURS => self.latched.hours,
+            select::DAY_LOW => self.latched.days as u8,
+            select::DAY_HIGH => self.latched.day_high(),
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.sync();
+
+        match register {
+            select::SECONDS => {
+                self.clock.seconds = value & 0x3f;
+                self.cycles = 0;
+            }
+            select::MINUTES => self.clock.minutes = value & 0x3f,
+            select::HOURS => self.clock.hours = value & 0x1f,
+            select::DAY_LOW => self.clock.days = (self.clock.days & 0x100) | value as u16,
+            select::DAY_HIGH => self.clock.set_day_high(value),
+            _ => {}
+        }
+
+        // writes are visible to reads without needing another latch
+        self.latched = self.clock;
+    }
+}
+
+fn host_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+impl Snapshot for RtcRegisters {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.seconds);
+        w.u8(self.minutes);
+        w.u8(self.hours);
+        w.u16(self.days);
+        w.bool(self.halt);
+        w.bool(self.carry);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.seconds = r.u8()?;
+        self.minutes = r.u8()?;
+        self.hours = r.u8()?;
+        self.days = r.u16()?;
+        self.halt = r.bool()?;
+        self.carry = r.bool()?;
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Rtc {
+    fn save(&self, w: &mut StateWriter) {
+        Snapshot::save(&self.clock, w);
+        Snapshot::save(&self.latched, w);
+        w.u8(self.latch);
+        w.u32(self.cycles);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        Snapshot::load(&mut self.clock, r)?;
+        Snapshot::load(&mut self.latched, r)?;
+        self.latch = r.u8()?;
+        self.cycles = r.u32()?;
+
+        // the clock continues from the state, not from when it was last synced
+        self.synced_at = host_time();
+
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 #[derive(Default)]
 pub struct Joypad {
     directions: bool,
@@ -10,26 +12,52 @@ pub struct Joypad {
     b: bool,
     select: bool,
     start: bool,
+    /// a selected line went from high to low
+    pub interrupt: bool,
 }
 
 impl Joypad {
     pub fn set_matrix(&mut self, directions: bool, actions: bool) {
+        let lines = self.lines();
+
         self.directions = directions;
         self.actions = actions;
+
+        self.detect_edge(lines);
     }
 
     pub fn set_directions(&mut self, right: bool, left: bool, up: bool, down: bool) {
+        let lines = self.lines();
+
         self.right = right;
         self.left = left;
         self.up = up;
         self.down = down;
+
+        self.detect_edge(lines);
     }
 
     pub fn set_actions(&mut self, a: bool, b: bool, select: bool, start: bool) {
+        let lines = self.lines();
+
         self.a = a;
         self.b = b;
         self.select = select;
         self.start = start;
+
+        self.detect_edge(lines);
+    }
+
+    /// input lines as read from JOYP, low when a selected button is held
+    fn lines(&self) -> u8 {
+        self.select_matrix() & 0x0f
+    }
+
+    /// raise the interrupt if any line that was high before the change is now low
+    fn detect_edge(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupt = true;
+        }
     }
 
     pub fn select_matrix(&self) -> u8 {
@@ -77,3 +105,35 @@ impl Joypad {
         a | b | se | st
     }
 }
+
+impl Snapshot for Joypad {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.directions);
+        w.bool(self.actions);
+        w.bool(self.right);
+        w.bool(self.left);
+        w.bool(self.up);
+        w.bool(self.down);
+        w.bool(self.a);
+        w.bool(self.b);
+        w.bool(self.select);
+        w.bool(self.start);
+        w.bool(self.interrupt);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.directions = r.bool()?;
+        self.actions = r.bool()?;
+        self.right = r.bool()?;
+        self.left = r.bool()?;
+        self.up = r.bool()?;
+        self.down = r.bool()?;
+        self.a = r.bool()?;
+        self.b = r.bool()?;
+        self.select = r.bool()?;
+        self.start = r.bool()?;
+        self.interrupt = r.bool()?;
+
+        Ok(())
+    }
+}
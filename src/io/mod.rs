@@ -2,11 +2,18 @@ pub mod apu;
 pub mod cartridge;
 pub mod joypad;
 pub mod ppu;
+pub mod serial;
 pub mod timer;
 
-use crate::{boot, cpu::interrupt::Interrupt};
+use std::mem;
 
-use cartridge::Cartridge;
+use crate::{
+    boot,
+    cpu::interrupt::Interrupt,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+use cartridge::{Cartridge, CgbFlag};
 
 use log::warn;
 pub use ppu::Ppu;
@@ -17,6 +24,7 @@ use self::{
     apu::Apu,
     joypad::Joypad,
     ppu::{Lcdc, PpuStatus},
+    serial::Serial,
 };
 
 mod map {
@@ -36,6 +44,9 @@ mod map {
     pub const WRAM_LOW: u16 = 0xc000;
     pub const WRAM_HIGH: u16 = 0xdfff;
     pub const WRAM_SIZE: usize = 0x2000;
+    /// wram is split into two banks, the second switchable between 1-7 on the cgb
+    pub const WRAM_BANK_SIZE: usize = WRAM_SIZE / 2;
+    pub const WRAM_BANKS: usize = 8;
     /// echo ram, mirror of 0xc000-0xddff
     pub const ECHO_LOW: u16 = 0xe000;
     pub const ECHO_HIGH: u16 = 0xfdff;
@@ -53,6 +64,11 @@ mod map {
         pub const JOYP_ADDR: u16 = 0xff00;
     }
 
+    pub mod serial_io {
+        pub const SB_ADDR: u16 = 0xff01;
+        pub const SC_ADDR: u16 = 0xff02;
+    }
+
     pub mod timer_io {
         pub const DIV_ADDR: u16 = 0xff04;
         pub const TIMA_ADDR: u16 = 0xff05;
@@ -89,6 +105,20 @@ mod map {
         pub const WAVE_SIZE: usize = 0x10;
     }
 
+    pub mod cgb_io {
+        pub const VBK_ADDR: u16 = 0xff4f;
+        pub const HDMA1_ADDR: u16 = 0xff51;
+        pub const HDMA2_ADDR: u16 = 0xff52;
+        pub const HDMA3_ADDR: u16 = 0xff53;
+        pub const HDMA4_ADDR: u16 = 0xff54;
+        pub const HDMA5_ADDR: u16 = 0xff55;
+        pub const BCPS_ADDR: u16 = 0xff68;
+        pub const BCPD_ADDR: u16 = 0xff69;
+        pub const OCPS_ADDR: u16 = 0xff6a;
+        pub const OCPD_ADDR: u16 = 0xff6b;
+        pub const SVBK_ADDR: u16 = 0xff70;
+    }
+
     pub mod lcd_io {
         pub const LCDC_ADDR: u16 = 0xff40;
         pub const STAT_ADDR: u16 = 0xff41;
@@ -106,6 +136,8 @@ mod map {
 
     /// interrupt flag (IF)
     pub const INTERRUPT_FLAG: u16 = 0xff0f;
+    /// cgb speed switch (KEY1)
+    pub const SPEED_SWITCH: u16 = 0xff4d;
     /// switch to unmap the bootrom
     pub const UNMAP_BOOTROM: u16 = 0xff50;
     /// interrupt enable (IE)
@@ -114,55 +146,131 @@ mod map {
 
 pub struct Bus {
     pub cart: Cartridge,
+    /// every wram bank, only the first two are used outside of cgb mode
     pub wram: Box<[u8]>,
+    /// bank mapped at 0xd000 (SVBK), 0 selects bank 1
+    pub wram_bank: u8,
     pub hram: Box<[u8]>,
     dma_src: u16,
     dma_idx: u16,
+    /// next address vram dma copies from
+    hdma_src: u16,
+    /// next offset into vram dma copies to
+    hdma_dst: u16,
+    /// blocks of 0x10 bytes left to copy, minus one
+    hdma_blocks: u8,
+    /// copying a block every hblank
+    hdma_hblank: bool,
+    /// cycles the cpu is held for while vram dma copies
+    pub dma_stall: u16,
     /// set of cpu interrupts, disrupt control flow
     pub it_enable: Interrupt,
+    /// interrupts requested by devices, waiting to be dispatched
     pub it_flag: Interrupt,
     pub apu: Apu,
     pub ppu: Ppu,
     pub joypad: Joypad,
+    pub serial: Serial,
     pub timer: Timer,
+    /// boot rom mapped over the start of the cartridge until unmapped
+    boot_rom: Box<[u8]>,
     pub boot: bool,
+    /// boot rom was unmapped by the last store, taken by the cpu to finish the boot
+    boot_unmapped: bool,
+    /// cartridge supports the gameboy color, enabling its registers
+    pub cgb: bool,
+    /// cpu, timer & serial run twice as fast as everything else
+    pub double_speed: bool,
+    /// speed switch armed through KEY1, performed by the next stop
+    speed_switch: bool,
+    /// in double speed, whether the slow half of the machine is clocked this cycle
+    slow_cycle: bool,
 }
 
 impl Bus {
     pub fn new(cart: Cartridge) -> Self {
+        let cgb = cart.header.cgb != CgbFlag::Dmg;
+
+        let mut ppu = Ppu::new();
+        ppu.cgb = cgb;
+
         Self {
             cart,
-            wram: Box::new([0; map::WRAM_SIZE]),
+            wram: vec![0; map::WRAM_BANK_SIZE * map::WRAM_BANKS].into_boxed_slice(),
+            wram_bank: 0,
             hram: Box::new([0; map::HRAM_SIZE]),
             dma_src: 0,
             dma_idx: map::OAM_SIZE as u16,
+            hdma_src: 0,
+            hdma_dst: 0,
+            hdma_blocks: 0x7f,
+            hdma_hblank: false,
+            dma_stall: 0,
             it_enable: Interrupt::from(0),
             it_flag: Interrupt::from(0),
             apu: Apu::new(),
-            ppu: Ppu::new(),
+            ppu,
             joypad: Joypad::default(),
+            serial: Serial::default(),
             timer: Timer::new(),
+            boot_rom: Box::new(boot::BOOTROM),
             boot: true,
+            boot_unmapped: false,
+            cgb,
+            double_speed: false,
+            speed_switch: false,
+            slow_cycle: false,
         }
     }
 
+    /// replace the built-in boot rom, e.g. with a dump of the original
+    pub fn set_boot_rom(&mut self, rom: Vec<u8>) {
+        self.boot_rom = rom.into_boxed_slice();
+    }
+
+    /// carry the boot rom, dmg palettes & serial link over from the bus being replaced
+    pub fn take_config(&mut self, old: &mut Bus) {
+        self.boot_rom = mem::take(&mut old.boot_rom);
+        self.ppu.dmg_palettes = old.ppu.dmg_palettes;
+        self.serial.take_link(&mut old.serial);
+    }
+
+    /// put the hardware into the state left behind by the boot rom
+    pub fn skip_boot(&mut self) {
+        self.store_byte(map::apu_io::NR52_ADDR, 0xf1);
+        self.store_byte(map::apu_io::NR51_ADDR, 0xf3);
+        self.store_byte(map::apu_io::NR50_ADDR, 0x77);
+        self.store_byte(map::lcd_io::LCDC_ADDR, 0x91);
+        self.store_byte(map::lcd_io::BGP_ADDR, 0xfc);
+        self.boot = false;
+    }
+
+    /// returns whether the boot rom was unmapped since the last call
+    pub fn take_boot_unmapped(&mut self) -> bool {
+        mem::take(&mut self.boot_unmapped)
+    }
+
     pub fn fetch_byte(&self, address: u16) -> u8 {
         match address {
             map::ROM_LOW..=map::ROM_HIGH => {
-                if self.boot && (address as usize) < boot::BOOTROM.len() {
-                    boot::BOOTROM[address as usize]
+                if self.boot && (address as usize) < self.boot_rom.len() {
+                    self.boot_rom[address as usize]
                 } else {
                     self.cart.controller.fetch_rom_byte(address)
                 }
             }
-            map::VRAM_LOW..=map::VRAM_HIGH => self.ppu.vram[(address - map::VRAM_LOW) as usize],
+            map::VRAM_LOW..=map::VRAM_HIGH => {
+                self.ppu.vram[self.ppu.vram_index(address - map::VRAM_LOW)]
+            }
             map::XRAM_LOW..=map::XRAM_HIGH => {
                 self.cart.controller.fetch_ram_byte(address - map::XRAM_LOW)
             }
-            map::WRAM_LOW..=map::WRAM_HIGH => self.wram[(address - map::WRAM_LOW) as usize],
-            map::ECHO_LOW..=map::ECHO_HIGH => self.wram[(address - map::ECHO_LOW) as usize],
+            map::WRAM_LOW..=map::WRAM_HIGH => self.wram[self.wram_index(address - map::WRAM_LOW)],
+            map::ECHO_LOW..=map::ECHO_HIGH => self.wram[self.wram_index(address - map::ECHO_LOW)],
             map::OAM_LOW..=map::OAM_HIGH => self.ppu.oam[(address - map::OAM_LOW) as usize],
             map::joyp_io::JOYP_ADDR => self.joypad.select_matrix(),
+            map::serial_io::SB_ADDR => self.serial.data,
+            map::serial_io::SC_ADDR => self.serial.get_control(),
             map::timer_io::DIV_ADDR => self.timer.get_div(),
             map::timer_io::TIMA_ADDR => self.timer.counter,
             map::timer_io::TMA_ADDR => self.timer.modulo,
@@ -192,6 +300,20 @@ impl Bus {
             map::lcd_io::WX_ADDR => self.ppu.wx,
             map::HRAM_LOW..=map::HRAM_HIGH => self.hram[(address - map::HRAM_LOW) as usize],
             map::INTERRUPT_ENABLE => self.it_enable.into(),
+            // unused upper bits always read as set
+            map::INTERRUPT_FLAG => 0xe0 | u8::from(self.it_flag),
+            map::SPEED_SWITCH if self.cgb => {
+                0x7e | (self.double_speed as u8) << 7 | self.speed_switch as u8
+            }
+            map::cgb_io::VBK_ADDR if self.cgb => 0xfe | self.ppu.vram_bank,
+            map::cgb_io::HDMA5_ADDR if self.cgb => {
+                (!self.hdma_hblank as u8) << 7 | self.hdma_blocks
+            }
+            map::cgb_io::BCPS_ADDR if self.cgb => self.ppu.bg_palettes.get_spec(),
+            map::cgb_io::BCPD_ADDR if self.cgb => self.ppu.bg_palettes.get_data(),
+            map::cgb_io::OCPS_ADDR if self.cgb => self.ppu.obj_palettes.get_spec(),
+            map::cgb_io::OCPD_ADDR if self.cgb => self.ppu.obj_palettes.get_data(),
+            map::cgb_io::SVBK_ADDR if self.cgb => 0xf8 | self.wram_bank,
             _ => {
                 warn!("attempt to read from unmapped memory `0x{:04x}`", address);
                 0xff
@@ -199,12 +321,81 @@ impl Bus {
         }
     }
 
-    pub fn switch_speed(&mut self) {}
+    /// position in `wram` of an offset into 0xc000-0xdfff
+    fn wram_index(&self, offset: u16) -> usize {
+        let offset = offset as usize;
+
+        if offset < map::WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.wram_bank.max(1) as usize * map::WRAM_BANK_SIZE + offset - map::WRAM_BANK_SIZE
+        }
+    }
+
+    /// start or stop vram dma through HDMA5
+    fn set_hdma_control(&mut self, value: u8) {
+        if self.hdma_hblank && value & 0x80 == 0 {
+            // cancelled, the remaining length can still be read back
+            self.hdma_hblank = false;
+            return;
+        }
+
+        self.hdma_blocks = value & 0x7f;
+
+        if value & 0x80 != 0 {
+            self.hdma_hblank = true;
+        } else {
+            // general purpose dma copies everything at once while the cpu waits
+            for _ in 0..=self.hdma_blocks {
+                self.hdma_block();
+            }
+        }
+    }
+
+    /// copy 0x10 bytes into vram, holding the cpu for as long as it takes
+    fn hdma_block(&mut self) {
+        for _ in 0..0x10 {
+            let value = self.fetch_byte(self.hdma_src);
+            let index = self.ppu.vram_index(self.hdma_dst);
+            self.ppu.vram[index] = value;
+
+            self.hdma_src = self.hdma_src.wrapping_add(1);
+            self.hdma_dst = (self.hdma_dst + 1) & 0x1fff;
+        }
+
+        // 8 machine cycles at normal speed, which is twice as many cpu cycles in double speed
+        self.dma_stall += 32 << self.double_speed as u16;
+
+        if self.hdma_blocks == 0 {
+            self.hdma_hblank = false;
+        }
+
+        self.hdma_blocks = self.hdma_blocks.wrapping_sub(1) & 0x7f;
+    }
+
+    /// perform a speed switch if one was armed, returns whether it was
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch {
+            return false;
+        }
+
+        self.speed_switch = false;
+        self.double_speed = !self.double_speed;
+
+        true
+    }
 
     pub fn tick(&mut self) {
         self.timer.tick();
-        self.ppu.tick();
-        self.apu.tick();
+        self.serial.tick();
+
+        // in double speed the lcd, sound & cartridge only see every other cycle
+        self.slow_cycle = !self.slow_cycle;
+        if !self.double_speed || self.slow_cycle {
+            self.cart.tick();
+            self.ppu.tick();
+            self.apu.tick();
+        }
 
         if self.dma_idx < map::OAM_SIZE as u16 {
             let direct_byte = self.fetch_byte(self.dma_src);
@@ -213,6 +404,21 @@ impl Bus {
             self.dma_idx += 1;
             self.dma_src += 1;
         }
+
+        if mem::take(&mut self.ppu.hblank_started) && self.hdma_hblank {
+            self.hdma_block();
+        }
+
+        self.collect_interrupts();
+    }
+
+    /// move interrupts requested by devices into IF
+    pub fn collect_interrupts(&mut self) {
+        self.it_flag.vblank |= mem::take(&mut self.ppu.vblank_int);
+        self.it_flag.lcdc |= mem::take(&mut self.ppu.lcd_stat_int);
+        self.it_flag.timer |= mem::take(&mut self.timer.interrupt);
+        self.it_flag.serial |= mem::take(&mut self.serial.interrupt);
+        self.it_flag.joypad |= mem::take(&mut self.joypad.interrupt);
     }
 
     pub fn store_byte(&mut self, address: u16, value: u8) {
@@ -221,7 +427,8 @@ impl Bus {
                 self.cart.controller.store_rom_byte(address, value);
             }
             map::VRAM_LOW..=map::VRAM_HIGH => {
-                self.ppu.vram[(address - map::VRAM_LOW) as usize] = value;
+                let index = self.ppu.vram_index(address - map::VRAM_LOW);
+                self.ppu.vram[index] = value;
             }
             map::XRAM_LOW..=map::XRAM_HIGH => {
                 self.cart
@@ -229,10 +436,12 @@ impl Bus {
                     .store_ram_byte(address - map::XRAM_LOW, value);
             }
             map::WRAM_LOW..=map::WRAM_HIGH => {
-                self.wram[(address - map::WRAM_LOW) as usize] = value;
+                let index = self.wram_index(address - map::WRAM_LOW);
+                self.wram[index] = value;
             }
             map::ECHO_LOW..=map::ECHO_HIGH => {
-                self.wram[(address - map::ECHO_LOW) as usize] = value;
+                let index = self.wram_index(address - map::ECHO_LOW);
+                self.wram[index] = value;
             }
             map::OAM_LOW..=map::OAM_HIGH => {
                 self.ppu.oam[(address - map::OAM_LOW) as usize] = value;
@@ -246,6 +455,8 @@ impl Bus {
                 let most = (matrix_col_2x2 & 2) != 0;
                 self.joypad.set_matrix(most, least);
             }
+            map::serial_io::SB_ADDR => self.serial.data = value,
+            map::serial_io::SC_ADDR => self.serial.set_control(value),
             map::timer_io::DIV_ADDR => self.timer.reset_div(),
             map::timer_io::TIMA_ADDR => self.timer.counter = value,
             map::timer_io::TMA_ADDR => self.timer.modulo = value,
@@ -295,9 +506,32 @@ impl Bus {
             map::lcd_io::WX_ADDR => {
                 self.ppu.wx = value;
             }
+            map::SPEED_SWITCH if self.cgb => {
+                self.speed_switch = value & 0x01 != 0;
+            }
+            map::cgb_io::VBK_ADDR if self.cgb => self.ppu.vram_bank = value & 0x01,
+            map::cgb_io::HDMA1_ADDR if self.cgb => {
+                self.hdma_src = (value as u16) << 8 | (self.hdma_src & 0x00f0);
+            }
+            map::cgb_io::HDMA2_ADDR if self.cgb => {
+                self.hdma_src = (self.hdma_src & 0xff00) | (value & 0xf0) as u16;
+            }
+            map::cgb_io::HDMA3_ADDR if self.cgb => {
+                self.hdma_dst = ((value & 0x1f) as u16) << 8 | (self.hdma_dst & 0x00f0);
+            }
+            map::cgb_io::HDMA4_ADDR if self.cgb => {
+                self.hdma_dst = (self.hdma_dst & 0x1f00) | (value & 0xf0) as u16;
+            }
+            map::cgb_io::HDMA5_ADDR if self.cgb => self.set_hdma_control(value),
+            map::cgb_io::BCPS_ADDR if self.cgb => self.ppu.bg_palettes.set_spec(value),
+            map::cgb_io::BCPD_ADDR if self.cgb => self.ppu.bg_palettes.set_data(value),
+            map::cgb_io::OCPS_ADDR if self.cgb => self.ppu.obj_palettes.set_spec(value),
+            map::cgb_io::OCPD_ADDR if self.cgb => self.ppu.obj_palettes.set_data(value),
+            map::cgb_io::SVBK_ADDR if self.cgb => self.wram_bank = value & 0x07,
             map::UNMAP_BOOTROM => {
                 if self.boot && value == 1 {
                     self.boot = false;
+                    self.boot_unmapped = true;
                 }
             }
             map::HRAM_LOW..=map::HRAM_HIGH => {
@@ -312,3 +546,45 @@ impl Bus {
         }
     }
 }
+
+impl Snapshot for Bus {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.wram);
+        w.u8(self.wram_bank);
+        w.bytes(&self.hram);
+        w.u16(self.dma_src);
+        w.u16(self.dma_idx);
+        w.u16(self.hdma_src);
+        w.u16(self.hdma_dst);
+        w.u8(self.hdma_blocks);
+        w.bool(self.hdma_hblank);
+        w.u16(self.dma_stall);
+        w.u8(self.it_enable.into());
+        w.u8(self.it_flag.into());
+        w.bool(self.boot);
+        w.bool(self.double_speed);
+        w.bool(self.speed_switch);
+        w.bool(self.slow_cycle);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.wram)?;
+        self.wram_bank = r.u8()? & 0x07;
+        r.bytes_into(&mut self.hram)?;
+        self.dma_src = r.u16()?;
+        self.dma_idx = r.u16()?;
+        self.hdma_src = r.u16()?;
+        self.hdma_dst = r.u16()? & 0x1fff;
+        self.hdma_blocks = r.u8()? & 0x7f;
+        self.hdma_hblank = r.bool()?;
+        self.dma_stall = r.u16()?;
+        self.it_enable = Interrupt::from(r.u8()?);
+        self.it_flag = Interrupt::from(r.u8()?);
+        self.boot = r.bool()?;
+        self.double_speed = r.bool()?;
+        self.speed_switch = r.bool()?;
+        self.slow_cycle = r.bool()?;
+
+        Ok(())
+    }
+}
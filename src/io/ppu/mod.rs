@@ -1,8 +1,15 @@
-pub use self::{lcdc::Lcdc, status::PpuStatus};
+pub use self::{
+    lcdc::Lcdc,
+    palette::{CgbPalettes, ComboButton, ComboDirection, DmgPalettes, PaletteError, Preset},
+    status::PpuStatus,
+};
+
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 use super::map::{OAM_SIZE, VRAM_SIZE};
 
 pub mod lcdc;
+pub mod palette;
 pub mod status;
 
 mod timing {
@@ -16,29 +23,6 @@ pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 pub const SCANLINE_MAX: usize = 153;
 
-const DMG_PALETTE: [Colour; 4] = [
-    Colour {
-        r: 0xe0,
-        g: 0xf8,
-        b: 0xd0,
-    }, // 100%
-    Colour {
-        r: 0x88,
-        g: 0xc0,
-        b: 0x70,
-    }, // 66%
-    Colour {
-        r: 0x34,
-        g: 0x68,
-        b: 0x56,
-    }, // 33%
-    Colour {
-        r: 0x08,
-        g: 0x18,
-        b: 0x20,
-    }, // 0%
-];
-
 #[derive(Clone, Copy)]
 enum PpuStage {
     HBlank,        // m0
@@ -47,6 +31,15 @@ enum PpuStage {
     PixelTransfer, // m3
 }
 
+/// background or window pixel, along with what decides whether sprites are drawn over it
+struct BackgroundPixel {
+    colour: Colour,
+    /// colour index before the palette is applied, sprites are always drawn over 0
+    id: u8,
+    /// cgb tile attribute putting the background above sprites
+    priority: bool,
+}
+
 #[derive(Clone, Copy, Default)]
 pub struct Colour {
     pub r: u8,
@@ -69,13 +62,30 @@ pub struct Ppu {
     pub stat: PpuStatus,
     pub vblank_int: bool,
     pub lcd_stat_int: bool,
+    /// both vram banks, the second is only used in cgb mode
     pub vram: Box<[u8]>,
+    /// bank mapped into the cpu's address space (VBK)
+    pub vram_bank: u8,
     pub oam: Box<[u8]>,
+    /// render with tile attributes & colour palettes
+    pub cgb: bool,
+    pub bg_palettes: CgbPalettes,
+    pub obj_palettes: CgbPalettes,
+    /// colours for the dmg palette registers
+    pub dmg_palettes: DmgPalettes,
     pub framebuffer: Box<[Colour]>,
     pub frame: u32,
+    /// hblank of a visible line just began, taken by the bus to run hdma
+    pub hblank_started: bool,
     ticks: u32,
 }
 
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Ppu {
     pub fn new() -> Self {
         Self {
@@ -93,10 +103,16 @@ impl Ppu {
             stat: PpuStatus::default(),
             vblank_int: false,
             lcd_stat_int: false,
-            vram: vec![0; VRAM_SIZE].into_boxed_slice(),
+            vram: vec![0; VRAM_SIZE * 2].into_boxed_slice(),
+            vram_bank: 0,
             oam: vec![0; OAM_SIZE].into_boxed_slice(),
+            cgb: false,
+            bg_palettes: CgbPalettes::default(),
+            obj_palettes: CgbPalettes::default(),
+            dmg_palettes: DmgPalettes::default(),
             framebuffer: vec![Colour::default(); LCD_WIDTH * LCD_HEIGHT].into_boxed_slice(),
             frame: 0,
+            hblank_started: false,
             ticks: 0,
         }
     }
@@ -108,17 +124,25 @@ impl Ppu {
     fn compare_ly_lyc(&mut self) {
         if self.ly == self.lyc {
             self.stat.coincidence_flag = true;
-            self.lcd_stat_int = true;
+
+            if self.stat.lyc_check {
+                self.lcd_stat_int = true;
+            }
         }
     }
 
     fn update_stat_interrupt(&mut self) {
-        self.lcd_stat_int = match self.stage {
+        let enabled = match self.stage {
             PpuStage::HBlank => self.stat.m0_hblank_interrupt,
             PpuStage::VBlank => self.stat.m1_vblank_interrupt,
             PpuStage::OamSearch => self.stat.m2_oam_interrupt,
             PpuStage::PixelTransfer => panic!("invalid lcd interrupt status"),
         };
+
+        // only ever requests, a lyc match on the same line must not be dropped
+        if enabled {
+            self.lcd_stat_int = true;
+        }
     }
 
     pub fn tick(&mut self) {
@@ -142,7 +166,7 @@ impl Ppu {
                     if self.ly < LCD_HEIGHT as u8 {
                         self.render_line();
                     }
-                    // TODO: hdma transfer
+                    self.hblank_started = self.ly < LCD_HEIGHT as u8;
                     self.stage = PpuStage::HBlank;
                     self.update_stat_interrupt();
                     self.ticks -= timing::PIXEL_TRANSFER as u32;
@@ -184,6 +208,11 @@ impl Ppu {
         self.ticks += 1;
     }
 
+    /// position in `vram` of an address in the bank mapped by VBK
+    pub fn vram_index(&self, offset: u16) -> usize {
+        self.vram_bank as usize * VRAM_SIZE + offset as usize
+    }
+
     fn get_pixel(&self, x: u32, y: u32) -> Colour {
         self.framebuffer[(x + y * LCD_WIDTH as u32) as usize]
     }
@@ -192,15 +221,16 @@ impl Ppu {
         self.framebuffer[(x + y * LCD_WIDTH as u32) as usize] = value;
     }
 
-    fn get_tile(&self, tile_id: u8, x: u8, y: u8, sprite: bool) -> u8 {
+    fn get_tile(&self, tile_id: u8, x: u8, y: u8, sprite: bool, bank: usize) -> u8 {
         let tile_addr: u16 = if self.lcdc.bg_win_map || sprite {
             tile_id as u16 * 16
         } else {
             (0x1000i32 + (16 * tile_id as i8 as i32)) as u16
         };
 
-        let line_1 = self.vram[(tile_addr + y as u16 * 2) as usize + 1];
-        let line_2 = self.vram[(tile_addr + y as u16 * 2) as usize];
+        let line_addr = bank * VRAM_SIZE + (tile_addr + y as u16 * 2) as usize;
+        let line_1 = self.vram[line_addr + 1];
+        let line_2 = self.vram[line_addr];
 
         let mask: u8 = 0x80 >> x;
 
@@ -211,7 +241,7 @@ impl Ppu {
         (palette >> (id * 2)) & 3
     }
 
-    fn render_background_pixel(&self, x: u8, y: u8, window: bool) -> Colour {
+    fn render_background_pixel(&self, x: u8, y: u8, window: bool) -> BackgroundPixel {
         let map_area: u16 = if (self.lcdc.bg_map && !window) || (self.lcdc.win_map && window) {
             0x1c00
         } else {
@@ -223,12 +253,43 @@ impl Ppu {
 
         let map_address = map_area + (tile_x as u16) + (tile_y as u16 * 32);
         let tile_id = self.vram[map_address as usize];
-        let tile = self.get_tile(tile_id, x % 8, y % 8, false);
 
-        DMG_PALETTE[self.convert_dmg_palette(self.bgp, tile) as usize]
+        if !self.cgb {
+            let id = self.get_tile(tile_id, x % 8, y % 8, false, 0);
+
+            return BackgroundPixel {
+                colour: self.dmg_palettes.bg[self.convert_dmg_palette(self.bgp, id) as usize],
+                id,
+                priority: false,
+            };
+        }
+
+        // attributes sit at the same position of the map in the second bank
+        let attributes = self.vram[VRAM_SIZE + map_address as usize];
+
+        let mut pixel_x = x % 8;
+        let mut pixel_y = y % 8;
+
+        if attributes & 0x20 > 0 {
+            pixel_x = 7 - pixel_x;
+        }
+
+        if attributes & 0x40 > 0 {
+            pixel_y = 7 - pixel_y;
+        }
+
+        let bank = (attributes & 0x08 > 0) as usize;
+        let id = self.get_tile(tile_id, pixel_x, pixel_y, false, bank);
+
+        BackgroundPixel {
+            colour: self.bg_palettes.colour(attributes & 0x07, id),
+            id,
+            priority: attributes & 0x80 > 0,
+        }
     }
 
-    fn render_sprite_pixel(&mut self, x: u8, y: u8) {
+    /// colour of the first sprite with an opaque pixel here, & whether it's behind the background
+    fn render_sprite_pixel(&self, x: u8, y: u8) -> Option<(Colour, bool)> {
         for i in 0..40 {
             let sprite_address = i * 4;
 
@@ -240,12 +301,6 @@ impl Ppu {
                     self.oam[sprite_address + 2] & if self.lcdc.obj_size { 0xfe } else { 0xff };
                 let sprite_tile_attributes = self.oam[sprite_address + 3];
 
-                let palette = if sprite_tile_attributes & 0x10 > 0 {
-                    self.obp1
-                } else {
-                    self.obp0
-                };
-
                 let sprite_height = if self.lcdc.obj_size { 16 } else { 8 };
 
                 if (y as i16) >= sprite_y
@@ -268,44 +323,147 @@ impl Ppu {
                         pixel_y = sprite_height - 1 - pixel_y;
                     }
 
-                    let sprite = self.get_tile(sprite_tile_id, pixel_x as u8, pixel_y as u8, true);
-                    let pixel = self.convert_dmg_palette(palette, sprite);
+                    let bank = (self.cgb && sprite_tile_attributes & 0x08 > 0) as usize;
+                    let sprite =
+                        self.get_tile(sprite_tile_id, pixel_x as u8, pixel_y as u8, true, bank);
 
-                    if sprite != 0 {
-                        self.set_pixel(x as u32, y as u32, DMG_PALETTE[pixel as usize]);
+                    if sprite == 0 {
+                        continue;
                     }
+
+                    let colour = if self.cgb {
+                        self.obj_palettes
+                            .colour(sprite_tile_attributes & 0x07, sprite)
+                    } else {
+                        let (palette, colours) = if sprite_tile_attributes & 0x10 > 0 {
+                            (self.obp1, &self.dmg_palettes.obj1)
+                        } else {
+                            (self.obp0, &self.dmg_palettes.obj0)
+                        };
+
+                        colours[self.convert_dmg_palette(palette, sprite) as usize]
+                    };
+
+                    return Some((colour, priority));
                 }
             }
         }
+
+        None
     }
 
     fn render_line(&mut self) {
         let window_x = self.wx as i32 - 7;
         let window_y = self.wy;
 
+        // in cgb mode the background is always drawn & this bit only takes away its priority
+        let bg_priority = !self.cgb || self.lcdc.bg_win_enable;
+
         for x in 0..LCD_WIDTH {
-            if self.lcdc.win_enable && self.ly >= window_y && x as i32 >= window_x {
-                let pixel = self.render_background_pixel(
+            let background = if self.lcdc.win_enable && self.ly >= window_y && x as i32 >= window_x
+            {
+                self.render_background_pixel(
                     (x as u8).wrapping_sub(window_x as u8),
                     (self.ly).wrapping_sub(window_y),
                     true,
-                );
-
-                self.set_pixel(x as u32, self.ly as u32, pixel);
-            } else if self.lcdc.bg_win_enable
-            /* TODO: change in cgb */
-            {
-                let pixel = self.render_background_pixel(
+                )
+            } else if self.lcdc.bg_win_enable || self.cgb {
+                self.render_background_pixel(
                     (x as u8).wrapping_add(self.scx),
                     (self.ly).wrapping_add(self.scy),
                     false,
-                );
+                )
+            } else {
+                BackgroundPixel {
+                    colour: self.dmg_palettes.bg[0],
+                    id: 0,
+                    priority: false,
+                }
+            };
+
+            let mut pixel = background.colour;
 
-                self.set_pixel(x as u32, self.ly as u32, pixel);
-            }
             if self.lcdc.obj_enable {
-                self.render_sprite_pixel(x as u8, self.ly);
+                if let Some((colour, behind)) = self.render_sprite_pixel(x as u8, self.ly) {
+                    let hidden =
+                        bg_priority && background.id != 0 && (behind || background.priority);
+
+                    if !hidden {
+                        pixel = colour;
+                    }
+                }
             }
+
+            self.set_pixel(x as u32, self.ly as u32, pixel);
         }
     }
 }
+
+impl Snapshot for Ppu {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.stage as u8);
+        w.u8(self.ly);
+        w.u8(self.lyc);
+        w.u8(self.scx);
+        w.u8(self.scy);
+        w.u8(self.wx);
+        w.u8(self.wy);
+        w.u8(self.bgp);
+        w.u8(self.obp0);
+        w.u8(self.obp1);
+        w.u8(self.lcdc.into());
+        w.u8(self.stat.into());
+        w.bool(self.vblank_int);
+        w.bool(self.lcd_stat_int);
+        w.bytes(&self.vram);
+        w.u8(self.vram_bank);
+        w.bytes(&self.oam);
+        self.bg_palettes.save(w);
+        self.obj_palettes.save(w);
+        for c in self.framebuffer.iter() {
+            w.bytes(&[c.r, c.g, c.b]);
+        }
+        w.u32(self.frame);
+        w.u32(self.ticks);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.stage = match r.u8()? {
+            0 => PpuStage::HBlank,
+            1 => PpuStage::VBlank,
+            2 => PpuStage::OamSearch,
+            3 => PpuStage::PixelTransfer,
+            _ => return Err(StateError::Invalid("ppu stage")),
+        };
+        self.ly = r.u8()?;
+        self.lyc = r.u8()?;
+        self.scx = r.u8()?;
+        self.scy = r.u8()?;
+        self.wx = r.u8()?;
+        self.wy = r.u8()?;
+        self.bgp = r.u8()?;
+        self.obp0 = r.u8()?;
+        self.obp1 = r.u8()?;
+        self.lcdc = Lcdc::from(r.u8()?);
+        self.stat = PpuStatus::from(r.u8()?);
+        self.vblank_int = r.bool()?;
+        self.lcd_stat_int = r.bool()?;
+        r.bytes_into(&mut self.vram)?;
+        self.vram_bank = r.u8()? & 0x01;
+        r.bytes_into(&mut self.oam)?;
+        self.bg_palettes.load(r)?;
+        self.obj_palettes.load(r)?;
+        for c in self.framebuffer.iter_mut() {
+            let rgb = r.bytes(3)?;
+            *c = Colour {
+                r: rgb[0],
+                g: rgb[1],
+                b: rgb[2],
+            };
+        }
+        self.frame = r.u32()?;
+        self.ticks = r.u32()?;
+
+        Ok(())
+    }
+}
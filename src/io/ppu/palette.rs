@@ -0,0 +1,460 @@
+use std::{error::Error, fmt};
+
+use crate::{
+    io::cartridge::CartridgeHeader,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+use super::Colour;
+
+/// bytes of colour data, 8 palettes of 4 colours at 2 bytes each
+const PALETTE_RAM_SIZE: usize = 64;
+
+/// gameboy color palette memory, accessed through a spec (BCPS/OCPS) & data (BCPD/OCPD) register
+pub struct CgbPalettes {
+    data: [u8; PALETTE_RAM_SIZE],
+    /// byte accessed through the data register
+    index: u8,
+    /// advance the index after every write to the data register
+    auto_increment: bool,
+}
+
+impl Default for CgbPalettes {
+    fn default() -> Self {
+        Self {
+            // power on with every colour white
+            data: [0xff; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+}
+
+impl CgbPalettes {
+    pub fn get_spec(&self) -> u8 {
+        0x40 | (self.auto_increment as u8) << 7 | self.index
+    }
+
+    pub fn set_spec(&mut self, value: u8) {
+        self.index = value & 0x3f;
+        self.auto_increment = value & 0x80 != 0;
+    }
+
+    pub fn get_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    pub fn set_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3f;
+        }
+    }
+
+    /// colour `id` of a palette, stored as little endian 15-bit bgr
+    pub fn colour(&self, palette: u8, id: u8) -> Colour {
+        let offset = (palette as usize & 0x07) * 8 + id as usize * 2;
+
+        rgb555(u16::from_le_bytes([
+            self.data[offset],
+            self.data[offset + 1],
+        ]))
+    }
+}
+
+/// built-in dmg colours, from lightest to darkest
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+    /// green tint of the original lcd
+    Classic,
+    /// grey lcd of the game boy pocket
+    Pocket,
+    /// backlit teal of the game boy light
+    Light,
+    /// plain greys, easiest to tell apart
+    Contrast,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [
+        Preset::Classic,
+        Preset::Pocket,
+        Preset::Light,
+        Preset::Contrast,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Classic => "classic",
+            Preset::Pocket => "pocket",
+            Preset::Light => "light",
+            Preset::Contrast => "contrast",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    /// the preset after this one, wrapping around, for cycling through them
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|preset| *preset == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn colours(self) -> [Colour; 4] {
+        let colours = match self {
+            Preset::Classic => [0xe0f8d0, 0x88c070, 0x346856, 0x081820],
+            Preset::Pocket => [0xc4cfa1, 0x8b956d, 0x4d533c, 0x1f1f1f],
+            Preset::Light => [0x00b581, 0x009a71, 0x00694a, 0x004f3b],
+            Preset::Contrast => [0xffffff, 0xaaaaaa, 0x555555, 0x000000],
+        };
+
+        colours.map(rgb888)
+    }
+}
+
+/// colours shown for each shade of BGP, OBP0 & OBP1 outside of cgb mode
+#[derive(Clone, Copy)]
+pub struct DmgPalettes {
+    pub bg: [Colour; 4],
+    pub obj0: [Colour; 4],
+    pub obj1: [Colour; 4],
+}
+
+/// direction held for a cgb boot combo
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ComboDirection {
+    Right,
+    Left,
+    Up,
+    Down,
+}
+
+/// button held along with the direction of a cgb boot combo
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ComboButton {
+    A,
+    B,
+}
+
+#[derive(Debug)]
+pub struct PaletteError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Default for DmgPalettes {
+    fn default() -> Self {
+        Preset::Classic.into()
+    }
+}
+
+impl From<Preset> for DmgPalettes {
+    fn from(preset: Preset) -> Self {
+        Self::uniform(preset.colours())
+    }
+}
+
+impl DmgPalettes {
+    /// the same colours for the background & both sprite palettes
+    pub fn uniform(colours: [Colour; 4]) -> Self {
+        Self {
+            bg: colours,
+            obj0: colours,
+            obj1: colours,
+        }
+    }
+
+    /// read palettes from lines of `<bg|obj0|obj1|all> = <colours>`, starting from the classic
+    /// preset
+    ///
+    /// colours are a preset name or four rgb hex values from lightest to darkest, like
+    /// `e0f8d0 88c070 346856 081820`, & lines starting with `#` are comments
+    pub fn parse(text: &str) -> Result<Self, PaletteError> {
+        let mut palettes = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| PaletteError {
+                line: i + 1,
+                message,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = line.split_once('=').ok_or_else(|| {
+                error(format!(
+                    "expected `<palette> = <colours>`, found `{}`",
+                    line
+                ))
+            })?;
+
+            let value = value.trim();
+            let colours = match Preset::from_name(value) {
+                Some(preset) => preset.colours(),
+                None => parse_colours(value)
+                    .ok_or_else(|| error(format!("invalid colours `{}`", value)))?,
+            };
+
+            match name.trim() {
+                "bg" => palettes.bg = colours,
+                "obj0" => palettes.obj0 = colours,
+                "obj1" => palettes.obj1 = colours,
+                "all" => palettes = Self::uniform(colours),
+                name => return Err(error(format!("unknown palette `{}`", name))),
+            }
+        }
+
+        Ok(palettes)
+    }
+
+    /// colourisation the cgb boot rom gives a dmg game, picked from a hash of its title
+    ///
+    /// only nintendo's own titles are recognised, everything else gets the default
+    pub fn compatibility(header: &CartridgeHeader) -> Self {
+        Self::combination(compatibility::title_combination(header))
+    }
+
+    /// colourisation chosen by holding a direction, optionally with a or b, during the cgb boot
+    /// logo
+    pub fn from_combo(direction: ComboDirection, button: Option<ComboButton>) -> Self {
+        let modifier = match button {
+            None => 0,
+            Some(ComboButton::A) => 1,
+            Some(ComboButton::B) => 2,
+        };
+
+        Self::combination(compatibility::BUTTON_COMBINATIONS[modifier * 4 + direction as usize])
+    }
+
+    fn combination(index: u8) -> Self {
+        let [obj0, obj1, bg] = compatibility::COMBINATIONS[index as usize];
+        let palette = |start: u8| {
+            std::array::from_fn(|i| {
+                let colour = start as usize + i;
+                rgb555(compatibility::COLOURS[colour / 4][colour % 4])
+            })
+        };
+
+        Self {
+            bg: palette(bg),
+            obj0: palette(obj0),
+            obj1: palette(obj1),
+        }
+    }
+}
+
+/// tables from the cgb boot rom used to colourise dmg games
+mod compatibility {
+    use crate::io::cartridge::{CartridgeHeader, Licensee};
+
+    /// palettes as 15-bit bgr, a few combinations start partway through one & run into the next
+    pub const COLOURS: [[u16; 4]; 30] = [
+        [0x7fff, 0x32bf, 0x00d0, 0x0000],
+        [0x639f, 0x4279, 0x15b0, 0x04cb],
+        [0x7fff, 0x6e31, 0x454a, 0x0000],
+        [0x7fff, 0x1bef, 0x0200, 0x0000],
+        [0x7fff, 0x421f, 0x1cf2, 0x0000],
+        [0x7fff, 0x5294, 0x294a, 0x0000],
+        [0x7fff, 0x03ff, 0x012f, 0x0000],
+        [0x7fff, 0x03ef, 0x01d6, 0x0000],
+        [0x7fff, 0x42b5, 0x3dc8, 0x0000],
+        [0x7e74, 0x03ff, 0x0180, 0x0000],
+        [0x67ff, 0x77ac, 0x1a13, 0x2d6b],
+        [0x7ed6, 0x4bff, 0x2175, 0x0000],
+        [0x53ff, 0x4a5f, 0x7e52, 0x0000],
+        [0x4fff, 0x7ed2, 0x3a4c, 0x1ce0],
+        [0x03ed, 0x7fff, 0x255f, 0x0000],
+        [0x036a, 0x021f, 0x03ff, 0x7fff],
+        [0x7fff, 0x01df, 0x0112, 0x0000],
+        [0x231f, 0x035f, 0x00f2, 0x0009],
+        [0x7fff, 0x03ea, 0x011f, 0x0000],
+        [0x299f, 0x001a, 0x000c, 0x0000],
+        [0x7fff, 0x027f, 0x001f, 0x0000],
+        [0x7fff, 0x03e0, 0x0206, 0x0120],
+        [0x7fff, 0x7eeb, 0x001f, 0x7c00],
+        [0x7fff, 0x3fff, 0x7e00, 0x001f],
+        [0x7fff, 0x03ff, 0x001f, 0x0000],
+        [0x03ff, 0x001f, 0x000c, 0x0000],
+        [0x7fff, 0x033f, 0x0193, 0x0000],
+        [0x0000, 0x4200, 0x037f, 0x7fff],
+        [0x7fff, 0x7e8c, 0x7c00, 0x0000],
+        [0x7fff, 0x1bef, 0x6180, 0x0000],
+    ];
+
+    /// first colour of the obj0, obj1 & bg palettes
+    pub const COMBINATIONS: [[u8; 3]; 51] = [
+        [16, 16, 116],
+        [72, 72, 72],
+        [80, 80, 80],
+        [96, 96, 96],
+        [36, 36, 36],
+        [0, 0, 0],
+        [108, 108, 108],
+        [20, 20, 20],
+        [48, 48, 48],
+        [104, 104, 104],
+        [64, 32, 32],
+        [16, 112, 112],
+        [16, 8, 8],
+        [12, 16, 16],
+        [16, 116, 116],
+        [112, 16, 112],
+        [8, 68, 8],
+        [64, 64, 32],
+        [16, 16, 28],
+        [16, 16, 72],
+        [16, 16, 80],
+        [76, 76, 36],
+        [15, 15, 44],
+        [68, 68, 8],
+        [16, 16, 8],
+        [16, 16, 12],
+        [112, 112, 0],
+        [12, 12, 0],
+        [0, 0, 4],
+        [72, 88, 72],
+        [80, 88, 80],
+        [96, 88, 96],
+        [64, 88, 32],
+        [68, 16, 52],
+        [111, 0, 56],
+        [111, 16, 60],
+        [76, 88, 36],
+        [64, 112, 40],
+        [16, 92, 112],
+        [68, 88, 8],
+        [16, 0, 8],
+        [16, 112, 12],
+        [112, 12, 0],
+        [12, 112, 16],
+        [84, 112, 16],
+        [12, 112, 0],
+        [100, 12, 112],
+        [0, 112, 32],
+        [16, 12, 112],
+        [112, 12, 24],
+        [16, 112, 116],
+    ];
+
+    /// combination for right, left, up & down alone, then with a, then with b
+    pub const BUTTON_COMBINATIONS: [u8; 12] = [1, 48, 5, 8, 0, 40, 43, 3, 6, 7, 28, 49];
+
+    /// sums of the recognised titles, the last ones share a sum & need the fourth letter too
+    const HASHES: [u8; 94] = [
+        0x00, 0x88, 0x16, 0x36, 0xd1, 0xdb, 0xf2, 0x3c, 0x8c, 0x92, 0x3d, 0x5c, 0x58, 0xc9, 0x3e,
+        0x70, 0x1d, 0x59, 0x69, 0x19, 0x35, 0xa8, 0x14, 0xaa, 0x75, 0x95, 0x99, 0x34, 0x6f, 0x15,
+        0xff, 0x97, 0x4b, 0x90, 0x17, 0x10, 0x39, 0xf7, 0xf6, 0xa2, 0x49, 0x4e, 0x43, 0x68, 0xe0,
+        0x8b, 0xf0, 0xce, 0x0c, 0x29, 0xe8, 0xb7, 0x86, 0x9a, 0x52, 0x01, 0x9d, 0x71, 0x9c, 0xbd,
+        0x5d, 0x6d, 0x67, 0x3f, 0x6b, 0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66,
+        0x6a, 0xbf, 0x0d, 0xf4, 0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a,
+        0xbf, 0x0d, 0xf4, 0xb3,
+    ];
+
+    /// index of the first hash that also needs the fourth letter
+    const FIRST_DUPLICATE: usize = 65;
+
+    const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+    /// combination used by each title in `HASHES`
+    const TITLE_COMBINATIONS: [u8; 94] = [
+        0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5,
+        29, 5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5,
+        42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11,
+        39, 18, 39, 24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+    ];
+
+    pub fn title_combination(header: &CartridgeHeader) -> u8 {
+        let nintendo = match &header.licensee {
+            Licensee::Old(code) => *code == 0x01,
+            Licensee::New(code) => code == "01",
+        };
+
+        if !nintendo {
+            return 0;
+        }
+
+        let title = &header.title_bytes;
+        let hash = title
+            .iter()
+            .fold(0u8, |hash, byte| hash.wrapping_add(*byte));
+
+        HASHES
+            .iter()
+            .enumerate()
+            .find(|&(i, &h)| {
+                h == hash
+                    && (i < FIRST_DUPLICATE || FOURTH_LETTERS[i - FIRST_DUPLICATE] == title[3])
+            })
+            .map_or(0, |(i, _)| TITLE_COMBINATIONS[i])
+    }
+}
+
+/// four whitespace separated hex colours, each optionally starting with `#`
+fn parse_colours(text: &str) -> Option<[Colour; 4]> {
+    let mut colours = [Colour::default(); 4];
+    let mut values = text.split_whitespace();
+
+    for colour in colours.iter_mut() {
+        let value = values.next()?;
+        let value = value.strip_prefix('#').unwrap_or(value);
+
+        if value.len() != 6 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        *colour = rgb888(u32::from_str_radix(value, 16).ok()?);
+    }
+
+    values.next().is_none().then_some(colours)
+}
+
+fn rgb888(value: u32) -> Colour {
+    Colour {
+        r: (value >> 16) as u8,
+        g: (value >> 8) as u8,
+        b: value as u8,
+    }
+}
+
+/// scale 5-bit channels up to 8 bits, so full intensity is pure white
+fn rgb555(value: u16) -> Colour {
+    let channel = |shift: u16| {
+        let c = (value >> shift & 0x1f) as u8;
+        c << 3 | c >> 2
+    };
+
+    Colour {
+        r: channel(0),
+        g: channel(5),
+        b: channel(10),
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for PaletteError {}
+
+impl Snapshot for CgbPalettes {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.data);
+        w.u8(self.index);
+        w.bool(self.auto_increment);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.data)?;
+        self.index = r.u8()? & 0x3f;
+        self.auto_increment = r.bool()?;
+
+        Ok(())
+    }
+}
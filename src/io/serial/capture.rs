@@ -0,0 +1,43 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::SerialLink;
+
+/// collects every byte sent over the link port, like the output of blargg's test roms
+///
+/// clones share the same output, so one can be connected while another is read
+#[derive(Clone, Default)]
+pub struct SerialCapture {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.output.borrow().clone()
+    }
+
+    /// output so far, invalid utf-8 is replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.output.borrow()).into_owned()
+    }
+
+    pub fn contains(&self, text: &str) -> bool {
+        self.text().contains(text)
+    }
+
+    pub fn clear(&self) {
+        self.output.borrow_mut().clear();
+    }
+}
+
+impl SerialLink for SerialCapture {
+    fn exchange(&mut self, value: u8) -> Option<u8> {
+        self.output.borrow_mut().push(value);
+
+        // nothing is driving the far end
+        Some(0xff)
+    }
+}
@@ -0,0 +1,312 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub use self::{capture::SerialCapture, printer::Printer, tcp::TcpLink};
+
+mod capture;
+mod printer;
+mod tcp;
+
+/// cycles per bit when using the internal 8192Hz clock
+pub const BIT_CYCLES: u16 = 512;
+
+/// device on the far end of the link cable
+pub trait SerialLink {
+    /// a transfer was started on our clock, returns the byte shifted back by the far end
+    /// or `None` if it will arrive later through `tick`
+    fn exchange(&mut self, value: u8) -> Option<u8>;
+
+    /// called every cycle with SB & whether we're waiting on the far end's clock,
+    /// returns the byte shifted in once the far end has clocked it or answered our transfer
+    fn tick(&mut self, data: u8, listening: bool) -> Option<u8> {
+        let _ = (data, listening);
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct Serial {
+    /// transfer data (SB)
+    pub data: u8,
+    /// transfer in progress, set by writing bit 7 of SC
+    transfer: bool,
+    /// this side drives the clock
+    internal_clock: bool,
+    /// byte being shifted in from the far end, `None` until it has answered
+    incoming: Option<u8>,
+    /// bits left in the current transfer
+    bits: u8,
+    /// cycles since the last bit was shifted
+    cycles: u16,
+    pub interrupt: bool,
+    /// far end of the cable, nothing connected reads as 0xff
+    link: Option<Box<dyn SerialLink>>,
+    /// far end is another emulated machine, shifting bits through `clock_external` & `shift`
+    wired: bool,
+    /// bits our clock has shifted that the far end hasn't answered yet
+    pending: u8,
+}
+
+impl Serial {
+    pub fn connect(&mut self, link: Box<dyn SerialLink>) {
+        self.link = Some(link);
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.link.take()
+    }
+
+    /// take over the far end of the cable from the port being replaced
+    pub fn take_link(&mut self, old: &mut Serial) {
+        self.link = old.link.take();
+        self.set_wired(old.wired);
+    }
+
+    /// hand bit level transfers to the caller instead of a `SerialLink`
+    pub fn set_wired(&mut self, wired: bool) {
+        self.wired = wired;
+        self.pending = 0;
+    }
+
+    /// returns whether our clock has shifted a bit that needs answering with `shift`
+    pub fn take_clock(&mut self) -> bool {
+        if self.pending == 0 {
+            return false;
+        }
+
+        self.pending -= 1;
+        true
+    }
+
+    /// bit currently on the serial out line
+    pub fn out_bit(&self) -> u8 {
+        self.data >> 7
+    }
+
+    /// the far end's clock shifted a bit in, returns the bit shifted out
+    pub fn clock_external(&mut self, bit: u8) -> u8 {
+        // nothing is shifted unless a transfer is waiting on the far end, the line idles high
+        if !self.transfer || self.internal_clock {
+            return 1;
+        }
+
+        self.shift(bit)
+    }
+
+    /// shift a bit in at the bottom, completing the transfer after 8
+    pub fn shift(&mut self, bit: u8) -> u8 {
+        let out = self.out_bit();
+
+        self.data = self.data << 1 | bit & 1;
+        self.bits = self.bits.saturating_sub(1);
+
+        if self.bits == 0 {
+            self.complete();
+        }
+
+        out
+    }
+
+    pub fn get_control(&self) -> u8 {
+        0x7e | (self.transfer as u8) << 7 | self.internal_clock as u8
+    }
+
+    pub fn set_control(&mut self, value: u8) {
+        self.transfer = value & 0x80 != 0;
+        self.internal_clock = value & 1 != 0;
+
+        if !self.transfer {
+            return;
+        }
+
+        self.bits = 8;
+        self.cycles = 0;
+        self.pending = 0;
+
+        if self.internal_clock && !self.wired {
+            self.incoming = match &mut self.link {
+                Some(link) => link.exchange(self.data),
+                None => Some(0xff),
+            };
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let listening = self.transfer && !self.internal_clock;
+
+        let incoming = self
+            .link
+            .as_mut()
+            .and_then(|link| link.tick(self.data, listening));
+
+        if !self.transfer {
+            return;
+        }
+
+        if self.wired {
+            if self.internal_clock && self.bits > self.pending {
+                self.cycles += 1;
+
+                if self.cycles >= BIT_CYCLES {
+                    self.cycles = 0;
+                    self.pending += 1;
+                }
+            }
+
+            return;
+        }
+
+        if !self.internal_clock {
+            // the far end clocks all 8 bits in before we notice
+            if let Some(value) = incoming {
+                self.data = value;
+                self.complete();
+            }
+
+            return;
+        }
+
+        if incoming.is_some() {
+            self.incoming = incoming;
+        }
+
+        if self.bits > 0 {
+            self.cycles += 1;
+
+            if self.cycles < BIT_CYCLES {
+                return;
+            }
+
+            self.cycles = 0;
+
+            // shift out the top bit while the far end's bit comes in at the bottom,
+            // the line idles high until the far end has answered
+            let bit = self
+                .incoming
+                .map_or(1, |incoming| incoming >> (self.bits - 1) & 1);
+            self.data = self.data << 1 | bit;
+            self.bits -= 1;
+        }
+
+        // a late answer replaces whatever was shifted in while waiting for it
+        if self.bits == 0 {
+            if let Some(value) = self.incoming {
+                self.data = value;
+                self.complete();
+            }
+        }
+    }
+
+    fn complete(&mut self) {
+        self.transfer = false;
+        self.interrupt = true;
+    }
+}
+
+impl Snapshot for Serial {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.data);
+        w.bool(self.transfer);
+        w.bool(self.internal_clock);
+        w.bool(self.incoming.is_some());
+        w.u8(self.incoming.unwrap_or(0xff));
+        w.u8(self.bits);
+        w.u16(self.cycles);
+        w.bool(self.interrupt);
+        w.u8(self.pending);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.data = r.u8()?;
+        self.transfer = r.bool()?;
+        self.internal_clock = r.bool()?;
+        let answered = r.bool()?;
+        let incoming = r.u8()?;
+        self.incoming = answered.then_some(incoming);
+        self.bits = r.u8()?;
+        self.cycles = r.u16()?;
+        self.interrupt = r.bool()?;
+        self.pending = r.u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// far end that only answers through `tick`, some cycles after the transfer starts
+    struct LateLink {
+        cycles: usize,
+        value: u8,
+    }
+
+    impl SerialLink for LateLink {
+        fn exchange(&mut self, _value: u8) -> Option<u8> {
+            None
+        }
+
+        fn tick(&mut self, _data: u8, _listening: bool) -> Option<u8> {
+            self.cycles = self.cycles.checked_sub(1)?;
+            (self.cycles == 0).then_some(self.value)
+        }
+    }
+
+    fn start(data: u8, control: u8) -> Serial {
+        let mut serial = Serial {
+            data,
+            ..Default::default()
+        };
+        serial.set_control(control);
+        serial
+    }
+
+    fn run(serial: &mut Serial, cycles: u16) {
+        for _ in 0..cycles {
+            serial.tick();
+        }
+    }
+
+    #[test]
+    fn internal_clock_without_link_reads_ff() {
+        let mut serial = start(0x12, 0x81);
+
+        run(&mut serial, 8 * BIT_CYCLES - 1);
+        assert!(!serial.interrupt);
+
+        run(&mut serial, 1);
+        assert!(serial.interrupt);
+        assert_eq!(serial.data, 0xff);
+        assert_eq!(serial.get_control() & 0x80, 0);
+    }
+
+    #[test]
+    fn late_answer_replaces_shifted_bits() {
+        let mut serial = Serial::default();
+        serial.connect(Box::new(LateLink {
+            cycles: 10 * BIT_CYCLES as usize,
+            value: 0x34,
+        }));
+        serial.data = 0x12;
+        serial.set_control(0x81);
+
+        // every bit has been shifted, but the transfer waits on the far end
+        run(&mut serial, 8 * BIT_CYCLES);
+        assert!(!serial.interrupt);
+        assert_eq!(serial.data, 0xff);
+
+        run(&mut serial, 2 * BIT_CYCLES);
+        assert!(serial.interrupt);
+        assert_eq!(serial.data, 0x34);
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_far_end() {
+        let mut serial = start(0x12, 0x80);
+
+        run(&mut serial, 16 * BIT_CYCLES);
+        assert!(!serial.interrupt);
+        assert_eq!(serial.data, 0x12);
+        assert_eq!(serial.get_control() & 0x80, 0x80);
+    }
+}
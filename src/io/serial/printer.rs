@@ -0,0 +1,513 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::PathBuf,
+};
+
+use log::{error, info};
+
+use super::SerialLink;
+
+/// printout width in pixels, a row of 20 tiles
+const PAPER_WIDTH: usize = 160;
+/// bytes in a row of 20 2bpp tiles
+const TILE_ROW_BYTES: usize = 20 * 16;
+/// image memory, enough for a full screen of 18 tile rows
+const BUFFER_SIZE: usize = 0x1680;
+/// how long the head stays busy after a print command, about a second
+const PRINT_CYCLES: u32 = 4_194_304;
+
+/// grey levels of the thermal paper, from white to black
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+mod command {
+    pub const INIT: u8 = 0x01;
+    pub const PRINT: u8 = 0x02;
+    pub const DATA: u8 = 0x04;
+    pub const BREAK: u8 = 0x08;
+    pub const STATUS: u8 = 0x0f;
+}
+
+mod status {
+    pub const CHECKSUM_ERROR: u8 = 0x01;
+    pub const PRINTING: u8 = 0x02;
+    pub const IMAGE_FULL: u8 = 0x04;
+    pub const UNPROCESSED: u8 = 0x08;
+    pub const PACKET_ERROR: u8 = 0x10;
+}
+
+/// byte of the packet the printer expects next
+#[derive(Clone, Copy)]
+enum Stage {
+    MagicHigh,
+    MagicLow,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// game boy printer, turning printouts into png files
+///
+/// games send packets of `88 33 <command> <compression> <length> <data> <checksum>` followed
+/// by two bytes, during which the printer answers with 0x81 & its status
+pub struct Printer {
+    directory: PathBuf,
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    /// sum of every byte from the command up to the checksum
+    checksum: u16,
+    received_checksum: u16,
+    /// tile data waiting to be printed
+    buffer: Vec<u8>,
+    /// errors from the last packet
+    errors: u8,
+    /// cycles until the current print finishes
+    busy: u32,
+    /// shades of the page being printed, fed out once a margin follows it
+    paper: Vec<u8>,
+    /// pages fed out, written to files once the print finishes rather than mid-packet
+    pages: Vec<Vec<u8>>,
+}
+
+impl Printer {
+    /// printouts are written to `directory` as numbered png files
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            stage: Stage::MagicHigh,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            buffer: Vec::new(),
+            errors: 0,
+            busy: 0,
+            paper: Vec::new(),
+            pages: Vec::new(),
+        }
+    }
+
+    /// take one byte of a packet, returns the byte shifted back
+    fn receive(&mut self, value: u8) -> u8 {
+        let mut response = 0x00;
+
+        self.stage = match self.stage {
+            Stage::MagicHigh if value == 0x88 => Stage::MagicLow,
+            Stage::MagicHigh => Stage::MagicHigh,
+            Stage::MagicLow if value == 0x33 => Stage::Command,
+            Stage::MagicLow if value == 0x88 => Stage::MagicLow,
+            Stage::MagicLow => Stage::MagicHigh,
+            Stage::Command => {
+                self.command = value;
+                self.checksum = value as u16;
+                Stage::Compression
+            }
+            Stage::Compression => {
+                self.compressed = value & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                Stage::LengthLow
+            }
+            Stage::LengthLow => {
+                self.length = value as u16;
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                Stage::LengthHigh
+            }
+            Stage::LengthHigh => {
+                self.length |= (value as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                self.data.clear();
+
+                if self.length == 0 {
+                    Stage::ChecksumLow
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::Data => {
+                self.data.push(value);
+                self.checksum = self.checksum.wrapping_add(value as u16);
+
+                if self.data.len() == self.length as usize {
+                    Stage::ChecksumLow
+                } else {
+                    Stage::Data
+                }
+            }
+            Stage::ChecksumLow => {
+                self.received_checksum = value as u16;
+                Stage::ChecksumHigh
+            }
+            Stage::ChecksumHigh => {
+                self.received_checksum |= (value as u16) << 8;
+                self.execute();
+                Stage::Alive
+            }
+            Stage::Alive => {
+                response = 0x81;
+                Stage::Status
+            }
+            Stage::Status => {
+                response = self.status();
+                Stage::MagicHigh
+            }
+        };
+
+        response
+    }
+
+    fn execute(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.errors = status::CHECKSUM_ERROR;
+            return;
+        }
+
+        self.errors = 0;
+
+        match self.command {
+            command::INIT => {
+                self.buffer.clear();
+                self.busy = 0;
+            }
+            command::PRINT => self.print(),
+            // an empty data packet only marks the end of the image
+            command::DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    std::mem::take(&mut self.data)
+                };
+
+                let space = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(space));
+            }
+            command::BREAK => self.buffer.clear(),
+            command::STATUS => {}
+            _ => self.errors = status::PACKET_ERROR,
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = self.errors;
+
+        if self.busy > 0 {
+            status |= status::PRINTING;
+        }
+        if self.buffer.len() >= BUFFER_SIZE {
+            status |= status::IMAGE_FULL;
+        }
+        if !self.buffer.is_empty() {
+            status |= status::UNPROCESSED;
+        }
+
+        status
+    }
+
+    /// print the buffer with `[sheets, margins, palette, exposure]` from the packet
+    fn print(&mut self) {
+        let [sheets, margins, palette, ..] = self.data[..] else {
+            self.errors = status::PACKET_ERROR;
+            return;
+        };
+
+        // some games send a blank palette & expect the usual one
+        let palette = if palette == 0 { 0xe4 } else { palette };
+        let rows = self.buffer.len() / TILE_ROW_BYTES * 8;
+
+        for _ in 0..sheets {
+            for y in 0..rows {
+                for x in 0..PAPER_WIDTH {
+                    let tile = y / 8 * 20 + x / 8;
+                    let offset = tile * 16 + y % 8 * 2;
+                    let bit = 7 - x % 8;
+
+                    let low = self.buffer[offset] >> bit & 1;
+                    let high = self.buffer[offset + 1] >> bit & 1;
+                    let colour = high << 1 | low;
+
+                    self.paper
+                        .push(SHADES[(palette >> (colour * 2) & 0x03) as usize]);
+                }
+            }
+        }
+
+        self.buffer.clear();
+        self.busy = PRINT_CYCLES;
+
+        // a margin after the image feeds the paper out, otherwise the next print continues it
+        if margins & 0x0f != 0 {
+            self.finish_page();
+        }
+    }
+
+    /// feed the printed page out, ready to be written
+    fn finish_page(&mut self) {
+        if !self.paper.is_empty() {
+            self.pages.push(std::mem::take(&mut self.paper));
+        }
+    }
+
+    /// write the pages fed out so far to the next free files in the output directory
+    fn write_pages(&mut self) {
+        for paper in std::mem::take(&mut self.pages) {
+            match self.write_page(&paper) {
+                Ok(path) => info!("printed to {}", path.display()),
+                Err(e) => error!("unable to write printout: {}", e),
+            }
+        }
+    }
+
+    fn write_page(&self, paper: &[u8]) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.directory)?;
+
+        let path = (1..)
+            .map(|n| self.directory.join(format!("print_{:03}.png", n)))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        let file = BufWriter::new(File::create(&path)?);
+
+        let height = paper.len() / PAPER_WIDTH;
+        let mut encoder = png::Encoder::new(file, PAPER_WIDTH as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(paper)?;
+
+        Ok(path)
+    }
+}
+
+impl SerialLink for Printer {
+    fn exchange(&mut self, value: u8) -> Option<u8> {
+        Some(self.receive(value))
+    }
+
+    fn tick(&mut self, _data: u8, _listening: bool) -> Option<u8> {
+        if self.busy > 0 {
+            self.busy -= 1;
+
+            if self.busy == 0 {
+                self.write_pages();
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for Printer {
+    /// don't lose a page that was never fed out
+    fn drop(&mut self) {
+        self.finish_page();
+        self.write_pages();
+    }
+}
+
+/// expand run length encoded data, a control byte with bit 7 set repeats the next byte
+/// `(control & 0x7f) + 2` times, otherwise `control + 1` literal bytes follow
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter().copied();
+
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(value) = bytes.next() else {
+                break;
+            };
+            output.extend(std::iter::repeat_n(value, (control & 0x7f) as usize + 2));
+        } else {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer() -> Printer {
+        Printer::new(std::env::temp_dir().join(format!("gameboy-printer-{}", std::process::id())))
+    }
+
+    /// send a whole packet, returns the alive & status bytes answered at its end
+    fn send(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+        let length = data.len() as u16;
+        let mut packet = vec![
+            0x88,
+            0x33,
+            command,
+            compression,
+            length as u8,
+            (length >> 8) as u8,
+        ];
+        packet.extend_from_slice(data);
+
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0x00, 0x00]);
+
+        let responses: Vec<u8> = packet
+            .into_iter()
+            .map(|byte| printer.receive(byte))
+            .collect();
+        assert!(responses[..responses.len() - 2]
+            .iter()
+            .all(|&byte| byte == 0x00));
+
+        (
+            responses[responses.len() - 2],
+            responses[responses.len() - 1],
+        )
+    }
+
+    /// a row of 20 tiles with every pixel in colour 1
+    fn tile_row() -> Vec<u8> {
+        [0xff, 0x00].repeat(TILE_ROW_BYTES / 2)
+    }
+
+    #[test]
+    fn decompress_runs_and_literals() {
+        let data = decompress(&[0x81, 0xaa, 0x01, 0x12, 0x34, 0x83, 0x00]);
+
+        assert_eq!(
+            data,
+            [0xaa, 0xaa, 0xaa, 0x12, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn decompress_stops_at_a_truncated_run() {
+        assert_eq!(decompress(&[0x00, 0x12, 0x85]), [0x12]);
+    }
+
+    #[test]
+    fn init_data_print_status() {
+        let mut printer = printer();
+
+        assert_eq!(send(&mut printer, command::INIT, 0, &[]), (0x81, 0x00));
+        assert_eq!(
+            send(&mut printer, command::DATA, 0, &tile_row()),
+            (0x81, status::UNPROCESSED)
+        );
+        assert_eq!(
+            send(&mut printer, command::DATA, 0, &[]),
+            (0x81, status::UNPROCESSED)
+        );
+        assert_eq!(
+            send(&mut printer, command::PRINT, 0, &[0x01, 0x01, 0xe4, 0x40]),
+            (0x81, status::PRINTING)
+        );
+        assert_eq!(
+            send(&mut printer, command::STATUS, 0, &[]),
+            (0x81, status::PRINTING)
+        );
+
+        let pages = std::mem::take(&mut printer.pages);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].len(), PAPER_WIDTH * 8);
+        assert!(pages[0].iter().all(|&shade| shade == SHADES[1]));
+    }
+
+    #[test]
+    fn compressed_data_fills_the_buffer() {
+        let mut printer = printer();
+
+        // the tile row as 160 two byte literals
+        let data = [0x01, 0xff, 0x00].repeat(TILE_ROW_BYTES / 2);
+        send(&mut printer, command::DATA, 0x01, &data);
+
+        assert_eq!(printer.buffer, tile_row());
+    }
+
+    #[test]
+    fn print_applies_the_palette() {
+        let mut printer = printer();
+
+        send(&mut printer, command::DATA, 0, &tile_row());
+        send(&mut printer, command::PRINT, 0, &[0x01, 0x01, 0x1b, 0x40]);
+
+        let pages = std::mem::take(&mut printer.pages);
+        assert!(pages[0].iter().all(|&shade| shade == SHADES[2]));
+    }
+
+    #[test]
+    fn print_without_margin_continues_the_page() {
+        let mut printer = printer();
+
+        send(&mut printer, command::DATA, 0, &tile_row());
+        send(&mut printer, command::PRINT, 0, &[0x01, 0x00, 0xe4, 0x40]);
+        assert!(printer.pages.is_empty());
+
+        send(&mut printer, command::DATA, 0, &tile_row());
+        send(&mut printer, command::PRINT, 0, &[0x01, 0x10, 0xe4, 0x40]);
+        assert!(printer.pages.is_empty());
+
+        send(&mut printer, command::DATA, 0, &tile_row());
+        send(&mut printer, command::PRINT, 0, &[0x01, 0x03, 0xe4, 0x40]);
+
+        let pages = std::mem::take(&mut printer.pages);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].len(), PAPER_WIDTH * 8 * 3);
+    }
+
+    #[test]
+    fn bad_checksum_is_reported_and_ignored() {
+        let mut printer = printer();
+
+        // a data packet with one byte & a checksum of 0
+        let packet = [
+            0x88,
+            0x33,
+            command::DATA,
+            0x00,
+            0x01,
+            0x00,
+            0x12,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        let responses: Vec<u8> = packet.map(|byte| printer.receive(byte)).to_vec();
+
+        assert_eq!(responses[9..], [0x81, status::CHECKSUM_ERROR]);
+        assert!(printer.buffer.is_empty());
+
+        // the next good packet clears the error
+        assert_eq!(send(&mut printer, command::STATUS, 0, &[]), (0x81, 0x00));
+    }
+
+    #[test]
+    fn pages_are_written_once_the_print_finishes() {
+        let mut printer = printer();
+        printer.pages.push(vec![SHADES[0]; PAPER_WIDTH]);
+        printer.busy = 2;
+
+        printer.tick(0, false);
+        assert_eq!(printer.pages.len(), 1);
+
+        let directory = printer.directory.clone();
+        let _ = fs::remove_dir_all(&directory);
+
+        printer.tick(0, false);
+        assert!(printer.pages.is_empty());
+        assert!(directory.join("print_001.png").exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use log::{error, info};
+
+use super::SerialLink;
+
+/// cycles between syncs, the length of a transfer on the internal clock
+const SYNC_CYCLES: u32 = super::BIT_CYCLES as u32 * 8;
+
+mod flags {
+    /// sender started a transfer on its own clock since the last sync
+    pub const STARTED: u8 = 0x01;
+    /// sender is waiting on the far end's clock
+    pub const LISTENING: u8 = 0x02;
+}
+
+/// link cable to another emulator over tcp
+///
+/// both sides stop every `SYNC_CYCLES` to swap what their serial port is doing, transfers
+/// are resolved from those messages alone so the outcome doesn't depend on host timing
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    /// cycles since the last sync
+    cycles: u32,
+    /// byte we started a transfer with since the last sync
+    started: Option<u8>,
+}
+
+impl TcpLink {
+    /// wait for the other emulator to connect
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!("waiting for link connection on port {}", port);
+
+        let (stream, address) = listener.accept()?;
+        info!("link connected from {}", address);
+
+        Self::new(stream)
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        info!("link connected to {}", stream.peer_addr()?);
+
+        Self::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // every sync is a round trip, don't let nagle batch them
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream: Some(stream),
+            cycles: 0,
+            started: None,
+        })
+    }
+
+    /// swap serial state with the far end, blocking until it reaches the same point
+    fn sync(&mut self, data: u8, listening: bool) -> io::Result<[u8; 3]> {
+        let Some(stream) = &mut self.stream else {
+            return Err(io::ErrorKind::NotConnected.into());
+        };
+
+        let mut message = [0, self.started.unwrap_or(0xff), data];
+        if self.started.is_some() {
+            message[0] |= flags::STARTED;
+        }
+        if listening {
+            message[0] |= flags::LISTENING;
+        }
+
+        stream.write_all(&message)?;
+        stream.read_exact(&mut message)?;
+
+        Ok(message)
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn exchange(&mut self, value: u8) -> Option<u8> {
+        if self.stream.is_none() {
+            return Some(0xff);
+        }
+
+        // answered at the next sync
+        self.started = Some(value);
+        None
+    }
+
+    fn tick(&mut self, data: u8, listening: bool) -> Option<u8> {
+        self.cycles += 1;
+
+        if self.cycles < SYNC_CYCLES {
+            return None;
+        }
+
+        self.cycles = 0;
+
+        let [peer_flags, peer_started, peer_data] = match self.sync(data, listening) {
+            Ok(message) => message,
+            Err(e) => {
+                if self.stream.take().is_some() {
+                    error!("link disconnected: {}", e);
+                }
+
+                // finish our transfer as if the cable was unplugged
+                return self.started.take().map(|_| 0xff);
+            }
+        };
+
+        if self.started.take().is_some() {
+            // far end only shifts its byte back if it was waiting for our clock
+            if peer_flags & flags::LISTENING != 0 {
+                Some(peer_data)
+            } else {
+                Some(0xff)
+            }
+        } else if listening && peer_flags & flags::STARTED != 0 {
+            Some(peer_started)
+        } else {
+            None
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub struct Timer {
     pub counter: u8,
     pub modulo: u8,
@@ -15,6 +17,12 @@ enum Divider {
     Div1024 = 10,
 }
 
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Timer {
     pub fn new() -> Self {
         Self {
@@ -76,3 +84,31 @@ impl Timer {
         ctrl
     }
 }
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.counter);
+        w.u8(self.modulo);
+        w.bool(self.enable);
+        w.u8(self.divider as u8);
+        w.u32(self.counter_16k);
+        w.bool(self.interrupt);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.u8()?;
+        self.modulo = r.u8()?;
+        self.enable = r.bool()?;
+        self.divider = match r.u8()? {
+            4 => Divider::Div16,
+            6 => Divider::Div64,
+            8 => Divider::Div256,
+            10 => Divider::Div1024,
+            _ => return Err(StateError::Invalid("timer divider")),
+        };
+        self.counter_16k = r.u32()?;
+        self.interrupt = r.bool()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+mod boot;
+pub mod cpu;
+mod emulator;
+pub mod headless;
+pub mod io;
+pub mod linked;
+pub mod rewind;
+pub mod state;
+
+pub use emulator::{Buttons, Emulator};
@@ -0,0 +1,141 @@
+use crate::{io::serial::Serial, Emulator};
+
+/// two machines joined by a link cable, stepped in lock-step within a single process
+pub struct LinkedPair {
+    machines: [Emulator; 2],
+    /// cycles run by each machine, the one behind is always stepped next
+    cycles: [usize; 2],
+}
+
+impl LinkedPair {
+    pub fn new(first: Emulator, second: Emulator) -> Self {
+        let mut machines = [first, second];
+
+        for machine in &mut machines {
+            machine.cpu_mut().bus.serial.set_wired(true);
+        }
+
+        Self {
+            machines,
+            cycles: [0; 2],
+        }
+    }
+
+    /// execute a single instruction on whichever machine is behind, returns the cycles taken
+    pub fn step(&mut self) -> u8 {
+        let index = if self.cycles[0] <= self.cycles[1] {
+            0
+        } else {
+            1
+        };
+        let cycles = self.machines[index].step_instruction();
+        self.cycles[index] += cycles as usize;
+
+        // keep the counters small, only the difference matters
+        let behind = self.cycles[0].min(self.cycles[1]);
+        self.cycles[0] -= behind;
+        self.cycles[1] -= behind;
+
+        let [first, second] = &mut self.machines;
+        let (first, second) = (
+            &mut first.cpu_mut().bus.serial,
+            &mut second.cpu_mut().bus.serial,
+        );
+
+        wire(first, second);
+        wire(second, first);
+
+        cycles
+    }
+
+    /// run until both machines have drawn a frame
+    pub fn run_frame(&mut self) {
+        let frames = [self.machines[0].frame(), self.machines[1].frame()];
+        let budget = self.machines[0].frame_cycles() + self.machines[1].frame_cycles();
+        let mut cycles = 0;
+
+        // each machine gets a frame's worth of cycles, in case its lcd is disabled
+        while (self.machines[0].frame() == frames[0] || self.machines[1].frame() == frames[1])
+            && cycles < budget
+        {
+            cycles += self.step() as usize;
+        }
+    }
+
+    pub fn machine(&self, index: usize) -> &Emulator {
+        &self.machines[index]
+    }
+
+    pub fn machine_mut(&mut self, index: usize) -> &mut Emulator {
+        &mut self.machines[index]
+    }
+
+    /// unplug the cable, returning both machines
+    pub fn into_inner(self) -> [Emulator; 2] {
+        let mut machines = self.machines;
+
+        for machine in &mut machines {
+            machine.cpu_mut().bus.serial.set_wired(false);
+        }
+
+        machines
+    }
+}
+
+/// answer each bit the master's clock has shifted, with the bit the other side shifts out
+fn wire(master: &mut Serial, other: &mut Serial) {
+    while master.take_clock() {
+        let bit = other.clock_external(master.out_bit());
+        master.shift(bit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::serial::BIT_CYCLES;
+
+    use super::*;
+
+    fn port(data: u8, control: u8) -> Serial {
+        let mut serial = Serial::default();
+        serial.set_wired(true);
+        serial.data = data;
+        serial.set_control(control);
+        serial
+    }
+
+    /// tick both ports & carry the bits between them, as `LinkedPair::step` does
+    fn run(first: &mut Serial, second: &mut Serial, cycles: u16) {
+        for _ in 0..cycles {
+            first.tick();
+            second.tick();
+            wire(first, second);
+            wire(second, first);
+        }
+    }
+
+    #[test]
+    fn wired_transfer_swaps_data() {
+        let mut master = port(0x12, 0x81);
+        let mut other = port(0x34, 0x80);
+
+        run(&mut master, &mut other, 8 * BIT_CYCLES - 1);
+        assert!(!master.interrupt && !other.interrupt);
+
+        run(&mut master, &mut other, 1);
+        assert!(master.interrupt && other.interrupt);
+        assert_eq!(master.data, 0x34);
+        assert_eq!(other.data, 0x12);
+    }
+
+    #[test]
+    fn idle_side_answers_ff() {
+        let mut master = port(0x12, 0x81);
+        let mut other = port(0x34, 0x00);
+
+        run(&mut master, &mut other, 8 * BIT_CYCLES);
+        assert!(master.interrupt && !other.interrupt);
+        assert_eq!(master.data, 0xff);
+        assert_eq!(other.data, 0x34);
+    }
+}
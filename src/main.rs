@@ -1,131 +1,335 @@
+#[cfg(feature = "frontend")]
 mod audio;
-mod boot;
-mod cpu;
-mod io;
-
-use std::time::Duration;
-use std::{path::Path, sync::Mutex};
-
-use crate::audio::{run_audio, Audio, AudioPacket};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpu::Cpu;
-use io::{
-    apu,
-    cartridge::Cartridge,
-    ppu::{LCD_HEIGHT, LCD_WIDTH},
+mod cli;
+#[cfg(feature = "frontend")]
+mod window;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
 };
-use log::error;
-use pixels::{Pixels, SurfaceTexture};
-use winit::{
-    dpi::LogicalSize,
-    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+
+use crate::cli::{CompatPalette, Options};
+use gameboy::{
+    headless::{self, InputScript},
+    io::{
+        cartridge::{Cartridge, CartridgeHeader},
+        ppu::DmgPalettes,
+        serial::{Printer, SerialCapture, TcpLink},
+    },
+    linked::LinkedPair,
+    Emulator,
 };
+use log::{error, info};
 
-const SCALE: f32 = 4.0;
+/// serial output of a failing test rom
+const SERIAL_FAILED: &str = "Failed";
+/// frames to keep running after a failure for its details, if no newline ends them first
+const FAILED_FRAMES: u32 = 60;
 
 fn main() {
     env_logger::init();
 
-    let mut cpu =
-        Cpu::new(Cartridge::from_path(Path::new("./roms/zelda.gb")).expect("unable to load rom"));
+    let options = Options::from_args();
+
+    let rom = read_rom(&options.rom);
+
+    if options.info {
+        match CartridgeHeader::parse(&rom) {
+            Ok(header) => print_header(&header),
+            Err(e) => {
+                error!("unable to parse header: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let surface_size = LogicalSize::new(LCD_WIDTH as f32, LCD_HEIGHT as f32);
-    let scaled_surface_size =
-        LogicalSize::new(surface_size.width * SCALE, surface_size.height * SCALE);
+    let mut emulator = load_emulator(rom, options.save_path(), &options);
 
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Rust GameBoy")
-        .with_inner_size(scaled_surface_size)
-        .with_min_inner_size(surface_size)
-        .build(&event_loop)
-        .unwrap();
+    if let Some(path) = &options.pair {
+        let second = load_emulator(read_rom(path), options.pair_save_path().unwrap(), &options);
+        let mut pair = LinkedPair::new(emulator, second);
+
+        if options.headless {
+            run_pair_headless(&mut pair, &options);
+        } else {
+            #[cfg(feature = "frontend")]
+            window::run_pair(pair, options);
+        }
+        return;
+    }
 
-    let window_size = window.inner_size();
+    // connect before running so both sides start in sync
+    let link = match (options.link_listen, &options.link_connect) {
+        (Some(port), _) => Some(TcpLink::listen(port)),
+        (_, Some(address)) => Some(TcpLink::connect(address.as_str())),
+        _ => None,
+    };
 
-    let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-    let mut pixels = Pixels::new(LCD_WIDTH as u32, LCD_HEIGHT as u32, surface_texture).unwrap();
+    match link {
+        Some(Ok(link)) => emulator.connect_serial(Box::new(link)),
+        Some(Err(e)) => {
+            error!("unable to connect link cable: {}", e);
+            std::process::exit(1);
+        }
+        None => {}
+    }
 
-    let mut last_frame = 0;
+    if let Some(directory) = &options.printer {
+        emulator.connect_serial(Box::new(Printer::new(directory)));
+    }
 
-    let mut right = false;
-    let mut left = false;
-    let mut up = false;
-    let mut down = false;
-    let mut a = false;
-    let mut b = false;
-    let mut select = false;
-    let mut start = false;
+    if options.headless {
+        run_headless(&mut emulator, &options);
+        return;
+    }
 
-    let audio = Audio::default();
-    let audio_tx = run_audio(audio.device, audio.config);
+    #[cfg(feature = "frontend")]
+    window::run(emulator, options);
+}
 
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::MainEventsCleared => {
-            window.request_redraw();
+fn read_rom(path: &Path) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            error!("unable to read rom: {}", e);
+            std::process::exit(1);
         }
-        Event::RedrawRequested(_) => {
-            cpu.bus.joypad.set_directions(right, left, up, down);
-            cpu.bus.joypad.set_actions(a, b, select, start);
-
-            while last_frame == cpu.bus.ppu.frame {
-                cpu.machine_cycle();
-                // if cpu.bus.apu.update {
-                //     audio_tx
-                //         .send(AudioPacket::Sample(cpu.bus.apu.output_left as f32 / 256.0))
-                //         .unwrap();
-                // }
-            }
+    }
+}
 
-            last_frame = cpu.bus.ppu.frame;
-
-            let frame = pixels.frame_mut();
-            for (c, pix) in cpu
-                .bus
-                .ppu
-                .framebuffer
-                .iter()
-                .zip(frame.chunks_exact_mut(4))
-            {
-                let output: [u8; 4] = [c.r, c.g, c.b, 255];
-                pix.copy_from_slice(&output);
-            }
+/// build a machine for the rom, with its save file & the boot options applied
+fn load_emulator(rom: Vec<u8>, save_path: PathBuf, options: &Options) -> Emulator {
+    let mut cart = match Cartridge::from_bytes(rom) {
+        Ok(cart) => cart,
+        Err(e) => {
+            error!("unable to load rom: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-            if let Err(e) = pixels.render() {
-                error!("unable to render: {}", e);
-                *control_flow = ControlFlow::Exit;
-            }
+    // save files & save states both land here, create it rather than failing every write
+    if let Some(dir) = &options.save_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            error!("unable to create save directory: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = cart.attach_save(save_path) {
+        error!("unable to read save file: {}", e);
+        std::process::exit(1);
+    }
+
+    cart.set_rtc_wall_clock(!options.headless);
+
+    let palettes = match (options.compat_palette, &options.palette_file) {
+        (Some(CompatPalette::Title), _) => Some(DmgPalettes::compatibility(&cart.header)),
+        (Some(CompatPalette::Combo(direction, button)), _) => {
+            Some(DmgPalettes::from_combo(direction, button))
         }
-        Event::WindowEvent { window_id, event } if window_id == window.id() => match event {
-            WindowEvent::CloseRequested => {
-                audio_tx.send(AudioPacket::Exiting).unwrap();
-                *control_flow = ControlFlow::Exit
+        (None, Some(path)) => match read_palettes(path) {
+            Some(palettes) => Some(palettes),
+            None => std::process::exit(1),
+        },
+        (None, None) => options.palette.map(DmgPalettes::from),
+    };
+
+    let mut emulator = Emulator::new(cart);
+
+    if let Some(palettes) = palettes {
+        emulator.set_dmg_palettes(palettes);
+    }
+
+    if let Some(path) = &options.boot_rom {
+        match fs::read(path) {
+            Ok(boot_rom) if boot_rom.len() == 0x100 => emulator.set_boot_rom(boot_rom),
+            Ok(boot_rom) => {
+                error!("boot rom must be 256 bytes, found {}", boot_rom.len());
+                std::process::exit(1);
             }
-            WindowEvent::Resized(size) => pixels.resize_surface(size.width, size.height).unwrap(),
-            WindowEvent::KeyboardInput {
-                device_id,
-                input,
-                is_synthetic,
-            } => {
-                if let Some(key) = input.virtual_keycode {
-                    let pressed = matches!(input.state, ElementState::Pressed);
-                    match key {
-                        VirtualKeyCode::Right => right = pressed,
-                        VirtualKeyCode::Left => left = pressed,
-                        VirtualKeyCode::Up => up = pressed,
-                        VirtualKeyCode::Down => down = pressed,
-                        VirtualKeyCode::X => a = pressed,
-                        VirtualKeyCode::Z => b = pressed,
-                        VirtualKeyCode::Back => select = pressed,
-                        VirtualKeyCode::Return => start = pressed,
-                        _ => {}
-                    }
-                }
+            Err(e) => {
+                error!("unable to read boot rom: {}", e);
+                std::process::exit(1);
             }
-            _ => {}
-        },
-        _ => {}
+        }
+    }
+
+    if options.skip_boot {
+        emulator.skip_boot();
+    }
+
+    emulator
+}
+
+/// run without a window as fast as possible, then print the framebuffer hash
+fn run_headless(emulator: &mut Emulator, options: &Options) {
+    let input = load_input(options.input.as_deref());
+    let capture = options
+        .until_serial
+        .is_some()
+        .then(|| emulator.capture_serial());
+
+    // frames run since the failure was reported, the details such as `#3` follow it
+    let mut failed_frames = None;
+
+    let mut finished = |capture: &SerialCapture| {
+        let text = capture.text();
+
+        if options
+            .until_serial
+            .as_ref()
+            .is_some_and(|until| text.contains(until.as_str()))
+        {
+            return true;
+        }
+
+        let Some(failed) = text.find(SERIAL_FAILED) else {
+            return false;
+        };
+
+        let frames = failed_frames.get_or_insert(0);
+        *frames += 1;
+
+        text[failed..].contains('\n') || *frames > FAILED_FRAMES
+    };
+
+    let frames = options.frames.unwrap_or_default();
+    let ran = headless::run_until(emulator, frames, &input, |_| {
+        capture.as_ref().is_some_and(&mut finished)
     });
+
+    info!("ran {} frames", ran.unwrap_or(frames));
+    println!(
+        "{:016x}",
+        headless::framebuffer_hash(emulator.framebuffer())
+    );
+
+    if let Some(path) = &options.screenshot {
+        if let Err(e) = headless::write_png(emulator.framebuffer(), path) {
+            error!("unable to write screenshot: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(e) = emulator.cartridge_mut().flush() {
+        error!("unable to write save file: {}", e);
+    }
+
+    if let (Some(until), Some(capture)) = (&options.until_serial, &capture) {
+        let text = capture.text();
+
+        if text.contains(until.as_str()) {
+            info!("serial output:\n{}", text);
+        } else if text.contains(SERIAL_FAILED) {
+            error!("test rom failed, serial output:\n{}", text);
+            std::process::exit(1);
+        } else {
+            error!(
+                "`{}` not sent within {} frames, serial output:\n{}",
+                until, frames, text
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// run both machines of a linked pair without a window, printing a hash for each
+fn run_pair_headless(pair: &mut LinkedPair, options: &Options) {
+    let inputs = [
+        load_input(options.input.as_deref()),
+        load_input(options.pair_input.as_deref()),
+    ];
+
+    let frames = options.frames.unwrap_or_default();
+    headless::run_pair(pair, frames, [&inputs[0], &inputs[1]]);
+
+    info!("ran {} frames", frames);
+    for index in 0..2 {
+        println!(
+            "{:016x}",
+            headless::framebuffer_hash(pair.machine(index).framebuffer())
+        );
+    }
+
+    if let Some(path) = &options.screenshot {
+        let framebuffers = [pair.machine(0).framebuffer(), pair.machine(1).framebuffer()];
+        if let Err(e) = headless::write_pair_png(framebuffers[0], framebuffers[1], path) {
+            error!("unable to write screenshot: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    for index in 0..2 {
+        if let Err(e) = pair.machine_mut(index).cartridge_mut().flush() {
+            error!("unable to write save file: {}", e);
+        }
+    }
+}
+
+fn load_input(path: Option<&Path>) -> InputScript {
+    let Some(path) = path else {
+        return InputScript::default();
+    };
+
+    match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| InputScript::parse(&text).map_err(|e| e.to_string()))
+    {
+        Ok(input) => input,
+        Err(e) => {
+            error!("unable to load input script: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// read a palette file, logging why it couldn't be used
+pub fn read_palettes(path: &Path) -> Option<DmgPalettes> {
+    let result = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| DmgPalettes::parse(&text).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(palettes) => Some(palettes),
+        Err(e) => {
+            error!("unable to load palette file: {}", e);
+            None
+        }
+    }
+}
+
+fn print_header(header: &CartridgeHeader) {
+    let checksum = |valid| if valid { "ok" } else { "bad" };
+
+    println!("title:           {}", header.title);
+    if let Some(manufacturer) = &header.manufacturer {
+        println!("manufacturer:    {}", manufacturer);
+    }
+    println!("licensee:        {}", header.licensee);
+    println!("cgb:             {}", header.cgb);
+    println!("sgb:             {}", header.sgb);
+    println!(
+        "type:            {} (0x{:02x})",
+        header.cartridge_type.name(),
+        header.cartridge_type.id
+    );
+    println!(
+        "rom:             {} KiB ({} banks)",
+        header.rom_size / 1024,
+        header.rom_banks
+    );
+    println!("ram:             {} bytes", header.ram_size);
+    println!("version:         {}", header.version);
+    println!(
+        "header checksum: 0x{:02x} ({})",
+        header.header_checksum,
+        checksum(header.verify_header_checksum())
+    );
+    println!(
+        "global checksum: 0x{:04x} ({})",
+        header.global_checksum,
+        checksum(header.verify_global_checksum())
+    );
 }
@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+
+use crate::Emulator;
+
+/// snapshots between keyframes, deltas are taken against the keyframe of their group
+const GROUP_SIZE: usize = 30;
+
+/// keyframe followed by the snapshots taken after it
+struct Group {
+    /// run length encoded snapshot
+    keyframe: Vec<u8>,
+    /// run length encoded xor of each snapshot against the keyframe
+    deltas: Vec<Vec<u8>>,
+}
+
+/// ring of recent machine snapshots, stepped backwards through to undo
+pub struct Rewind {
+    groups: VecDeque<Group>,
+    /// unencoded copy of the newest keyframe, deltas are computed against it
+    keyframe: Vec<u8>,
+    /// frames between snapshots
+    interval: u32,
+    /// snapshots kept before the oldest group is dropped
+    capacity: usize,
+    /// frames recorded since the last snapshot
+    frames: u32,
+}
+
+impl Rewind {
+    /// `capacity` snapshots taken every `interval` frames
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            groups: VecDeque::new(),
+            keyframe: Vec::new(),
+            interval: interval.max(1),
+            capacity: capacity.max(GROUP_SIZE),
+            frames: 0,
+        }
+    }
+
+    /// call once per frame, a snapshot is taken every `interval` calls
+    pub fn record(&mut self, emulator: &Emulator) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = emulator.save_state();
+
+        let full = self
+            .groups
+            .back()
+            .is_none_or(|group| group.deltas.len() + 1 >= GROUP_SIZE);
+
+        if full || state.len() != self.keyframe.len() {
+            self.groups.push_back(Group {
+                keyframe: encode(&state),
+                deltas: Vec::new(),
+            });
+            self.keyframe = state;
+        } else {
+            let delta: Vec<u8> = state
+                .iter()
+                .zip(&self.keyframe)
+                .map(|(a, b)| a ^ b)
+                .collect();
+
+            if let Some(group) = self.groups.back_mut() {
+                group.deltas.push(encode(&delta));
+            }
+        }
+
+        // drop whole groups, as their deltas are useless without the keyframe
+        while self.len() > self.capacity {
+            self.groups.pop_front();
+        }
+    }
+
+    /// restore the most recent snapshot & forget it, returns `false` once history runs out
+    pub fn rewind(&mut self, emulator: &mut Emulator) -> bool {
+        let Some(group) = self.groups.back_mut() else {
+            return false;
+        };
+
+        let state = match group.deltas.pop() {
+            Some(delta) => decode(&delta)
+                .iter()
+                .zip(&self.keyframe)
+                .map(|(a, b)| a ^ b)
+                .collect(),
+            None => {
+                let state = std::mem::take(&mut self.keyframe);
+                self.groups.pop_back();
+
+                // older deltas are against the previous keyframe
+                if let Some(group) = self.groups.back() {
+                    self.keyframe = decode(&group.keyframe);
+                }
+
+                state
+            }
+        };
+
+        self.frames = 0;
+
+        // snapshots were taken from this emulator, so they can only fail if it changed rom
+        if emulator.load_own_state(&state).is_err() {
+            self.clear();
+            return false;
+        }
+
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.keyframe.clear();
+        self.frames = 0;
+    }
+
+    /// number of snapshots held
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|group| group.deltas.len() + 1).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// bytes used by the encoded snapshots
+    pub fn memory_usage(&self) -> usize {
+        self.keyframe.len()
+            + self
+                .groups
+                .iter()
+                .map(|group| {
+                    group.keyframe.len() + group.deltas.iter().map(Vec::len).sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
+
+/// runs of zeros are stored as a zero followed by the run length
+fn encode(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        if data[i] != 0 {
+            output.push(data[i]);
+            i += 1;
+            continue;
+        }
+
+        let run = data[i..]
+            .iter()
+            .take(u8::MAX as usize)
+            .take_while(|byte| **byte == 0)
+            .count();
+
+        output.push(0);
+        output.push(run as u8);
+        i += run;
+    }
+
+    output
+}
+
+fn decode(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            0 => {
+                let run = bytes.next().copied().unwrap_or(0);
+                output.resize(output.len() + run as usize, 0);
+            }
+            _ => output.push(*byte),
+        }
+    }
+
+    output
+}
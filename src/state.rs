@@ -0,0 +1,242 @@
+use std::{error::Error, fmt};
+
+use crate::io::cartridge::CartridgeHeader;
+
+/// identifies a save state file
+const MAGIC: &[u8; 4] = b"GBST";
+/// bumped whenever the layout of any section changes
+pub const VERSION: u16 = 1;
+
+/// component state that can be written to & restored from a save state
+pub trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    /// file is not a save state
+    BadMagic,
+    /// state was written by an incompatible version
+    UnsupportedVersion(u16),
+    /// state belongs to a different rom
+    WrongRom { expected: String, found: String },
+    /// state is missing a required section
+    MissingSection([u8; 4]),
+    /// section ended before all of its fields were read
+    Truncated,
+    /// field holds a value that can't have been saved
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::WrongRom { expected, found } => write!(
+                f,
+                "save state is for `{}` but `{}` is loaded",
+                found, expected
+            ),
+            StateError::MissingSection(tag) => write!(
+                f,
+                "save state is missing the `{}` section",
+                String::from_utf8_lossy(tag)
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// fixed size memory, the reader must already know the length
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    /// variable size memory, prefixed with its length
+    pub fn sized_bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes(value);
+    }
+
+    /// tagged & length-prefixed so readers can find or skip it
+    pub fn section(&mut self, tag: &[u8; 4], component: &impl Snapshot) {
+        let mut section = StateWriter::default();
+        component.save(&mut section);
+
+        self.bytes(tag);
+        self.sized_bytes(&section.data);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(StateError::Truncated)?;
+
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// fill fixed size memory
+    pub fn bytes_into(&mut self, target: &mut [u8]) -> Result<(), StateError> {
+        target.copy_from_slice(self.bytes(target.len())?);
+        Ok(())
+    }
+
+    /// fill memory written with `sized_bytes`, which must be the same size
+    pub fn sized_bytes_into(&mut self, target: &mut [u8]) -> Result<(), StateError> {
+        if self.u32()? as usize != target.len() {
+            return Err(StateError::Invalid("memory size"));
+        }
+
+        self.bytes_into(target)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+}
+
+/// sections of a save state, after the header has been checked against the rom
+pub struct Sections<'a> {
+    sections: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> Sections<'a> {
+    pub fn parse(data: &'a [u8], header: &CartridgeHeader) -> Result<Self, StateError> {
+        let mut r = StateReader::new(data);
+
+        if r.bytes(MAGIC.len()).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let title_len = r.u8()? as usize;
+        let title = String::from_utf8_lossy(r.bytes(title_len)?).into_owned();
+        let header_checksum = r.u8()?;
+        let global_checksum = r.u16()?;
+
+        if title != header.title
+            || header_checksum != header.header_checksum
+            || global_checksum != header.global_checksum
+        {
+            return Err(StateError::WrongRom {
+                expected: header.title.clone(),
+                found: title,
+            });
+        }
+
+        let mut sections = Vec::new();
+
+        while !r.is_empty() {
+            let tag = r.array()?;
+            let len = r.u32()? as usize;
+            sections.push((tag, r.bytes(len)?));
+        }
+
+        Ok(Self { sections })
+    }
+
+    /// restore a component from its section, unknown sections are ignored
+    pub fn load(&self, tag: &[u8; 4], component: &mut impl Snapshot) -> Result<(), StateError> {
+        let (_, data) = self
+            .sections
+            .iter()
+            .find(|(t, _)| t == tag)
+            .ok_or(StateError::MissingSection(*tag))?;
+
+        component.load(&mut StateReader::new(data))
+    }
+}
+
+/// start a save state with the header identifying the rom it belongs to
+pub fn write_header(w: &mut StateWriter, header: &CartridgeHeader) {
+    let title = header.title.as_bytes();
+    let title = &title[..title.len().min(u8::MAX as usize)];
+
+    w.bytes(MAGIC);
+    w.u16(VERSION);
+    w.u8(title.len() as u8);
+    w.bytes(title);
+    w.u8(header.header_checksum);
+    w.u16(header.global_checksum);
+}
@@ -0,0 +1,363 @@
+use std::{fs, sync::mpsc::Sender};
+
+use crate::{
+    audio::{run_audio, Audio, AudioPacket},
+    cli::Options,
+    read_palettes,
+};
+use gameboy::{
+    io::ppu::{Colour, DmgPalettes, Preset, LCD_HEIGHT, LCD_WIDTH},
+    linked::LinkedPair,
+    rewind::Rewind,
+    Buttons, Emulator,
+};
+use log::{error, info};
+use pixels::{Pixels, SurfaceTexture};
+use winit::{
+    dpi::LogicalSize,
+    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+
+/// frames between rewind snapshots
+const REWIND_INTERVAL: u32 = 1;
+/// snapshots kept for rewinding, ten seconds worth
+const REWIND_CAPACITY: usize = 600;
+
+/// play a single machine in a window with audio, rewind & save states
+pub fn run(mut emulator: Emulator, options: Options) -> ! {
+    let event_loop = EventLoop::new();
+    let (window, mut pixels) = create_window(&event_loop, 1, options.scale);
+
+    let mut buttons = Buttons::default();
+    let mut frames = 0;
+    // fractional frames owed when running at a speed other than 1x
+    let mut frame_budget = 0.0;
+    // save state slot used by the save & load hotkeys
+    let mut slot = 1;
+    let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_CAPACITY);
+    let mut rewinding = false;
+    // preset being shown, cycled through with a hotkey
+    let mut preset = options.palette;
+
+    let audio_tx = start_audio(&options);
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::MainEventsCleared => {
+            window.request_redraw();
+        }
+        Event::RedrawRequested(_) => {
+            emulator.set_buttons(buttons);
+
+            if rewinding {
+                rewind.rewind(&mut emulator);
+            } else {
+                frame_budget += options.speed;
+                while frame_budget >= 1.0 {
+                    rewind.record(&emulator);
+                    emulator.run_frame();
+                    frame_budget -= 1.0;
+                    frames += 1;
+                }
+            }
+
+            let samples = emulator.drain_audio();
+
+            // audio is only played at normal speed, otherwise the stream would fall behind
+            let playing = options.speed == 1.0 && !rewinding;
+            if let Some(audio_tx) = audio_tx.as_ref().filter(|_| playing) {
+                play_samples(audio_tx, &samples);
+            }
+
+            draw(pixels.frame_mut(), emulator.framebuffer(), 0, LCD_WIDTH);
+
+            if let Err(e) = pixels.render() {
+                error!("unable to render: {}", e);
+                *control_flow = ControlFlow::Exit;
+            }
+
+            if options.frames.is_some_and(|limit| frames >= limit) {
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+        Event::WindowEvent { window_id, event } if window_id == window.id() => match event {
+            WindowEvent::CloseRequested => {
+                if let Err(e) = emulator.cartridge_mut().flush() {
+                    error!("unable to write save file: {}", e);
+                }
+                if let Some(audio_tx) = &audio_tx {
+                    audio_tx.send(AudioPacket::Exiting).unwrap();
+                }
+                *control_flow = ControlFlow::Exit
+            }
+            WindowEvent::Resized(size) => pixels.resize_surface(size.width, size.height).unwrap(),
+            WindowEvent::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic,
+            } => {
+                if let Some(key) = input.virtual_keycode {
+                    let pressed = matches!(input.state, ElementState::Pressed);
+                    match key {
+                        VirtualKeyCode::Right => buttons.right = pressed,
+                        VirtualKeyCode::Left => buttons.left = pressed,
+                        VirtualKeyCode::Up => buttons.up = pressed,
+                        VirtualKeyCode::Down => buttons.down = pressed,
+                        VirtualKeyCode::X => buttons.a = pressed,
+                        VirtualKeyCode::Z => buttons.b = pressed,
+                        VirtualKeyCode::Back => buttons.select = pressed,
+                        VirtualKeyCode::Return => buttons.start = pressed,
+                        VirtualKeyCode::R => rewinding = pressed,
+                        VirtualKeyCode::P if pressed => {
+                            let next = next_preset(preset);
+                            emulator.set_dmg_palettes(next.into());
+                            preset = Some(next);
+                        }
+                        VirtualKeyCode::O if pressed => {
+                            if let Some(palettes) = reload_palettes(&options) {
+                                emulator.set_dmg_palettes(palettes);
+                            }
+                        }
+                        VirtualKeyCode::F5 if pressed => save_state(&emulator, &options, slot),
+                        VirtualKeyCode::F8 if pressed => {
+                            load_state(&mut emulator, &options, slot);
+                            rewind.clear();
+                        }
+                        _ if pressed => {
+                            if let Some(digit) = state_slot(key) {
+                                slot = digit;
+                                info!("selected save state slot {}", slot);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    });
+}
+
+/// window showing `screens` lcds side by side
+fn create_window(event_loop: &EventLoop<()>, screens: usize, scale: f32) -> (Window, Pixels) {
+    let surface_size = LogicalSize::new((LCD_WIDTH * screens) as f32, LCD_HEIGHT as f32);
+    let scaled_surface_size =
+        LogicalSize::new(surface_size.width * scale, surface_size.height * scale);
+
+    let window = WindowBuilder::new()
+        .with_title("Rust GameBoy")
+        .with_inner_size(scaled_surface_size)
+        .with_min_inner_size(surface_size)
+        .build(event_loop)
+        .unwrap();
+
+    let window_size = window.inner_size();
+
+    let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+    let pixels = Pixels::new(
+        (LCD_WIDTH * screens) as u32,
+        LCD_HEIGHT as u32,
+        surface_texture,
+    )
+    .unwrap();
+
+    (window, pixels)
+}
+
+fn start_audio(options: &Options) -> Option<Sender<AudioPacket>> {
+    (!options.mute).then(|| {
+        let audio = Audio::default();
+        run_audio(audio.device, audio.config)
+    })
+}
+
+fn play_samples(audio_tx: &Sender<AudioPacket>, samples: &[i16]) {
+    // the output stream is mono, keep the left channel
+    for sample in samples.iter().step_by(2) {
+        let _ = audio_tx.send(AudioPacket::Sample(*sample as f32 / 256.0));
+    }
+}
+
+/// copy a framebuffer into the pixel buffer, starting `x` pixels across a `width` wide frame
+fn draw(frame: &mut [u8], framebuffer: &[Colour], x: usize, width: usize) {
+    for (y, row) in framebuffer.chunks(LCD_WIDTH).enumerate() {
+        let start = (y * width + x) * 4;
+        let line = &mut frame[start..start + LCD_WIDTH * 4];
+
+        for (c, pix) in row.iter().zip(line.chunks_exact_mut(4)) {
+            pix.copy_from_slice(&[c.r, c.g, c.b, 255]);
+        }
+    }
+}
+
+/// both machines of a linked pair in one window, without rewind or save states
+pub fn run_pair(mut pair: LinkedPair, options: Options) -> ! {
+    let event_loop = EventLoop::new();
+    let (window, mut pixels) = create_window(&event_loop, 2, options.scale);
+
+    let mut buttons = [Buttons::default(); 2];
+    let mut frames = 0;
+    let mut frame_budget = 0.0;
+    let mut preset = options.palette;
+
+    // only the first machine is heard
+    let audio_tx = start_audio(&options);
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::MainEventsCleared => {
+            window.request_redraw();
+        }
+        Event::RedrawRequested(_) => {
+            for (index, buttons) in buttons.iter().enumerate() {
+                pair.machine_mut(index).set_buttons(*buttons);
+            }
+
+            frame_budget += options.speed;
+            while frame_budget >= 1.0 {
+                pair.run_frame();
+                frame_budget -= 1.0;
+                frames += 1;
+            }
+
+            let samples = pair.machine_mut(0).drain_audio();
+            pair.machine_mut(1).drain_audio();
+
+            if let Some(audio_tx) = audio_tx.as_ref().filter(|_| options.speed == 1.0) {
+                play_samples(audio_tx, &samples);
+            }
+
+            for index in 0..2 {
+                let framebuffer = pair.machine(index).framebuffer();
+                draw(
+                    pixels.frame_mut(),
+                    framebuffer,
+                    index * LCD_WIDTH,
+                    LCD_WIDTH * 2,
+                );
+            }
+
+            if let Err(e) = pixels.render() {
+                error!("unable to render: {}", e);
+                *control_flow = ControlFlow::Exit;
+            }
+
+            if options.frames.is_some_and(|limit| frames >= limit) {
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+        Event::WindowEvent { window_id, event } if window_id == window.id() => match event {
+            WindowEvent::CloseRequested => {
+                for index in 0..2 {
+                    if let Err(e) = pair.machine_mut(index).cartridge_mut().flush() {
+                        error!("unable to write save file: {}", e);
+                    }
+                }
+                if let Some(audio_tx) = &audio_tx {
+                    audio_tx.send(AudioPacket::Exiting).unwrap();
+                }
+                *control_flow = ControlFlow::Exit
+            }
+            WindowEvent::Resized(size) => pixels.resize_surface(size.width, size.height).unwrap(),
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    let pressed = matches!(input.state, ElementState::Pressed);
+                    let [first, second] = &mut buttons;
+                    match key {
+                        VirtualKeyCode::Right => first.right = pressed,
+                        VirtualKeyCode::Left => first.left = pressed,
+                        VirtualKeyCode::Up => first.up = pressed,
+                        VirtualKeyCode::Down => first.down = pressed,
+                        VirtualKeyCode::X => first.a = pressed,
+                        VirtualKeyCode::Z => first.b = pressed,
+                        VirtualKeyCode::Back => first.select = pressed,
+                        VirtualKeyCode::Return => first.start = pressed,
+                        VirtualKeyCode::D => second.right = pressed,
+                        VirtualKeyCode::A => second.left = pressed,
+                        VirtualKeyCode::W => second.up = pressed,
+                        VirtualKeyCode::S => second.down = pressed,
+                        VirtualKeyCode::H => second.a = pressed,
+                        VirtualKeyCode::G => second.b = pressed,
+                        VirtualKeyCode::T => second.select = pressed,
+                        VirtualKeyCode::Y => second.start = pressed,
+                        VirtualKeyCode::P if pressed => {
+                            let next = next_preset(preset);
+                            for index in 0..2 {
+                                pair.machine_mut(index).set_dmg_palettes(next.into());
+                            }
+                            preset = Some(next);
+                        }
+                        VirtualKeyCode::O if pressed => {
+                            if let Some(palettes) = reload_palettes(&options) {
+                                for index in 0..2 {
+                                    pair.machine_mut(index).set_dmg_palettes(palettes);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    })
+}
+
+/// pick up edits to the palette file without restarting
+fn reload_palettes(options: &Options) -> Option<DmgPalettes> {
+    let path = options.palette_file.as_ref()?;
+    let palettes = read_palettes(path)?;
+
+    info!("reloaded palettes from {}", path.display());
+    Some(palettes)
+}
+
+/// preset after the one being shown, starting from the first if a preset isn't in use
+fn next_preset(preset: Option<Preset>) -> Preset {
+    let next = preset.map_or(Preset::ALL[0], Preset::next);
+
+    info!("switched to the {} palette", next.name());
+    next
+}
+
+fn save_state(emulator: &Emulator, options: &Options, slot: u8) {
+    let path = options.state_path(slot);
+
+    match fs::write(&path, emulator.save_state()) {
+        Ok(()) => info!("saved state to {}", path.display()),
+        Err(e) => error!("unable to write save state: {}", e),
+    }
+}
+
+fn load_state(emulator: &mut Emulator, options: &Options, slot: u8) {
+    let path = options.state_path(slot);
+
+    let result = fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| emulator.load_state(&data).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(()) => info!("loaded state from {}", path.display()),
+        Err(e) => error!("unable to load save state: {}", e),
+    }
+}
+
+/// number keys select the save state slot
+fn state_slot(key: VirtualKeyCode) -> Option<u8> {
+    let slot = match key {
+        VirtualKeyCode::Key1 => 1,
+        VirtualKeyCode::Key2 => 2,
+        VirtualKeyCode::Key3 => 3,
+        VirtualKeyCode::Key4 => 4,
+        VirtualKeyCode::Key5 => 5,
+        VirtualKeyCode::Key6 => 6,
+        VirtualKeyCode::Key7 => 7,
+        VirtualKeyCode::Key8 => 8,
+        VirtualKeyCode::Key9 => 9,
+        _ => return None,
+    };
+
+    Some(slot)
+}